@@ -3,6 +3,7 @@ import useAnchorProgram from "./hooks/useAnchorProgram";
 import { web3, BN } from "@coral-xyz/anchor";
 import { useWallet } from "@solana/wallet-adapter-react";
 import Link from "next/link";
+import { fetchLobby } from "./instructions";
 
 function truncateMiddle(
   str: string,
@@ -21,16 +22,27 @@ interface Board {
   maker: web3.PublicKey;
   guest: web3.PublicKey;
   state: number[];
-  gameOver: boolean;
+  // e.g. { ongoing: {} }, { whiteWins: {} }, { draw: {} }
+  result: Record<string, object>;
+  wager: BN;
+  // listed in the lobby by a challenge, which decides who may join
+  inLobby: boolean;
+}
+
+// Whether a board listed in the lobby still waits for its guest
+function isOpen(board: Board | null): boolean {
+  return !!board && board.inLobby && !board.guest && "ongoing" in board.result;
 }
 
 export default function BoardsList() {
   const { getProgram } = useAnchorProgram();
   const wallet = useWallet();
   const [boards, setBoards] = useState<
-    { pubkey: web3.PublicKey; account: Board }[]
+    { publicKey: web3.PublicKey; account: Board }[]
   >([]);
   const [loading, setLoading] = useState(false);
+  // Boards the wallet may join, or every board
+  const [joinableOnly, setJoinableOnly] = useState(true);
   if (!wallet) return; // wait for wallet to connect
   const walletPubkey = wallet.publicKey;
 
@@ -39,8 +51,21 @@ export default function BoardsList() {
       setLoading(true);
       try {
         const program = getProgram();
-        const boardAccounts = await program.account.board.all(); // async RPC call
-        setBoards(boardAccounts);
+        if (!joinableOnly) {
+          setBoards(await program.account.board.all()); // async RPC call
+          return;
+        }
+
+        // Only the boards of challenges admitting the wallet, not every board
+        const lobby = await fetchLobby(program, walletPubkey);
+        const pubkeys = lobby.map(({ account }) => account.board);
+        const accounts = await program.account.board.fetchMultiple(pubkeys);
+        setBoards(
+          pubkeys
+            .map((pubkey, i) => ({ pubkey, account: accounts[i] as Board | null }))
+            .filter(({ account }) => isOpen(account))
+            .map(({ pubkey, account }) => ({ publicKey: pubkey, account: account! }))
+        );
       } catch (err) {
         console.error("Failed to fetch boards:", err);
       } finally {
@@ -49,13 +74,31 @@ export default function BoardsList() {
     };
 
     fetchBoards();
-  }, [walletPubkey]); // runs once when wallet connects
+  }, [walletPubkey, joinableOnly]); // runs when wallet connects or the filter changes
+
+  const filter = (
+    <label className="flex gap-2">
+      <input
+        type="checkbox"
+        checked={joinableOnly}
+        onChange={(e) => setJoinableOnly(e.target.checked)}
+      />
+      Joinable only
+    </label>
+  );
 
   if (loading) return <div>Loading boards...</div>;
-  if (boards.length === 0) return <div>No boards found.</div>;
+  if (boards.length === 0)
+    return (
+      <div>
+        {filter}
+        No boards found.
+      </div>
+    );
 
   return (
     <div className="flex flex-col gap-8 flex-2">
+      {filter}
       {boards.map(({ publicKey: pubkey, account }) => (
         <div key={pubkey.toBase58()} className="border p-4 rounded">
           <p>
@@ -80,6 +123,11 @@ export default function BoardsList() {
               truncateMiddle(account.guest?.toBase58(), 6, 6)) ||
               "Open"}
           </p>
+          {!account.wager.isZero() && (
+            <p>
+              <strong>Wager:</strong> {account.wager.toString()}
+            </p>
+          )}
         </div>
       ))}
     </div>
@@ -16,7 +16,8 @@ const WalletMultiButtonDynamic = dynamic(
 
 interface Board {
   isWhiteTurn: boolean;
-  gameOver: boolean;
+  // e.g. { ongoing: {} }, { whiteWins: {} }, { draw: {} }
+  result: Record<string, object>;
   maker: web3.PublicKey;
   guest: web3.PublicKey;
   state: number[];
@@ -109,9 +110,9 @@ export default function Table({ searchParams }: PdaBoardProps) {
       );
 
       console.log("Piece moved:", signature);
-      const { isWhiteTurn, maker, guest, state, gameOver } =
+      const { isWhiteTurn, maker, guest, state, result } =
         await program.account.board.fetch(boardPda);
-      const onchainBoardState = { isWhiteTurn, maker, guest, state, gameOver };
+      const onchainBoardState = { isWhiteTurn, maker, guest, state, result };
       setBoardState(onchainBoardState);
       setStatus("Move successful ✅");
     } catch (err) {
@@ -180,7 +181,7 @@ export default function Table({ searchParams }: PdaBoardProps) {
         {isMakerWallet && (
           <button
             onClick={() => handleCloseBoard(pubkey)}
-            disabled={!wallet?.publicKey || loading || !boardState.gameOver}
+            disabled={!wallet?.publicKey || loading || "ongoing" in boardState.result}
             className={`px-4 py-2 rounded-xl text-white ${
               wallet?.publicKey
                 ? "bg-indigo-600 hover:bg-indigo-700"
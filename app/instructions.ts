@@ -1,26 +1,129 @@
 import { Program, web3, BN } from "@coral-xyz/anchor";
 import { AnchorChess } from "../target/types/anchor_chess";
 import { WalletSignTransactionError } from "@solana/wallet-adapter-base";
+import {
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
+  getAssociatedTokenAddressSync,
+} from "@solana/spl-token";
 
 const systemProgram = web3.SystemProgram.programId;
 const BOARD_SEED = Buffer.from("board");
+const HISTORY_SEED = Buffer.from("moves");
+const CONFIG_SEED = Buffer.from("config");
+const PROFILE_SEED = Buffer.from("profile");
+const CHALLENGE_SEED = Buffer.from("challenge");
+const BPF_LOADER_UPGRADEABLE = new web3.PublicKey(
+  "BPFLoaderUpgradeab1e11111111111111111111111"
+);
+
+// Program-wide settings: admin, fee, treasury and pause switch
+export function configPda(program: Program<AnchorChess>) {
+  const [config] = web3.PublicKey.findProgramAddressSync(
+    [CONFIG_SEED],
+    program.programId
+  );
+  return config;
+}
+
+// Move history recorded alongside each board
+export function historyPda(program: Program<AnchorChess>, board: web3.PublicKey) {
+  const [history] = web3.PublicKey.findProgramAddressSync(
+    [HISTORY_SEED, board.toBuffer()],
+    program.programId
+  );
+  return history;
+}
+
+// Rating and record of a player
+export function profilePda(program: Program<AnchorChess>, player: web3.PublicKey) {
+  const [profile] = web3.PublicKey.findProgramAddressSync(
+    [PROFILE_SEED, player.toBuffer()],
+    program.programId
+  );
+  return profile;
+}
+
+// Lobby listing of a board waiting for its guest
+export function challengePda(program: Program<AnchorChess>, board: web3.PublicKey) {
+  const [challenge] = web3.PublicKey.findProgramAddressSync(
+    [CHALLENGE_SEED, board.toBuffer()],
+    program.programId
+  );
+  return challenge;
+}
+
+// Profiles a rated game updates when it ends, null for unrated games
+async function ratedProfiles(program: Program<AnchorChess>, board: web3.PublicKey) {
+  const { rated, maker, guest } = await program.account.board.fetch(board);
+  if (!rated || !guest) {
+    return { whiteProfile: null, blackProfile: null };
+  }
+  return {
+    whiteProfile: profilePda(program, maker),
+    blackProfile: profilePda(program, guest),
+  };
+}
+
+// Accounts of a token wager in `mint`, all null for wagers in lamports:
+// the player's associated token account and the board's vault
+function tokenWagerAccounts(
+  mint: web3.PublicKey | null,
+  player: web3.PublicKey | null,
+  board: web3.PublicKey
+) {
+  if (!mint) {
+    return { mint: null, tokens: null, vault: null, tokenProgram: null };
+  }
+  return {
+    mint,
+    tokens: player ? getAssociatedTokenAddressSync(mint, player) : null,
+    vault: getAssociatedTokenAddressSync(mint, board, true),
+    tokenProgram: TOKEN_PROGRAM_ID,
+  };
+}
 
 // --- 1. Initialize board ---
 export async function initializeBoard(
   program: Program<AnchorChess>,
   maker: web3.PublicKey,
   seed: BN,
-  guest: web3.PublicKey | null
+  guest: web3.PublicKey | null,
+  // e.g. { fischer: { baseSeconds: 180, incrementSeconds: 2 } }, null for untimed
+  timeControl:
+    | { fischer: { baseSeconds: number; incrementSeconds: number } }
+    | { correspondence: { daysPerMove: number } }
+    | null = null,
+  // seconds an untimed game may sit idle, null for the program default
+  inactivityWindow: number | null = null,
+  // amount each player stakes, the guest must then be null
+  wager: BN = new BN(0),
+  // mint of a token wager, null to wager lamports
+  mint: web3.PublicKey | null = null,
+  // rated games need both players' profiles, see createProfile
+  rated = false
 ) {
   const [board] = web3.PublicKey.findProgramAddressSync(
     [BOARD_SEED, maker.toBuffer(), seed.toArrayLike(Buffer, "le", 8)],
     program.programId
   );
+  const { tokens, ...tokenAccounts } = tokenWagerAccounts(mint, maker, board);
 
   try {
     const signature = await program.methods
-      .initialize(seed, guest)
-      .accountsStrict({ maker, board, systemProgram })
+      .initialize(seed, guest, timeControl, inactivityWindow, wager, rated)
+      .accountsStrict({
+        maker,
+        board,
+        history: historyPda(program, board),
+        config: configPda(program),
+        makerProfile: rated ? profilePda(program, maker) : null,
+        guestProfile: rated && guest ? profilePda(program, guest) : null,
+        ...tokenAccounts,
+        makerTokens: tokens,
+        associatedTokenProgram: mint ? ASSOCIATED_TOKEN_PROGRAM_ID : null,
+        systemProgram,
+      })
       .rpc();
 
     return { signature, board, successful: true };
@@ -43,6 +146,47 @@ export async function initializeBoard(
   }
 }
 
+// --- 1b. Initialize board from a FEN position ---
+export async function initializeBoardFromPosition(
+  program: Program<AnchorChess>,
+  maker: web3.PublicKey,
+  seed: BN,
+  guest: web3.PublicKey | null,
+  // e.g. "8/8/4k3/8/8/8/8/R3K3 w - - 0 1"
+  fen: string,
+  timeControl:
+    | { fischer: { baseSeconds: number; incrementSeconds: number } }
+    | { correspondence: { daysPerMove: number } }
+    | null = null,
+  inactivityWindow: number | null = null,
+  wager: BN = new BN(0),
+  mint: web3.PublicKey | null = null
+) {
+  const [board] = web3.PublicKey.findProgramAddressSync(
+    [BOARD_SEED, maker.toBuffer(), seed.toArrayLike(Buffer, "le", 8)],
+    program.programId
+  );
+  const { tokens, ...tokenAccounts } = tokenWagerAccounts(mint, maker, board);
+
+  const signature = await program.methods
+    .initializeFromPosition(seed, guest, timeControl, inactivityWindow, wager, fen)
+    .accountsStrict({
+      maker,
+      board,
+      history: historyPda(program, board),
+      config: configPda(program),
+      makerProfile: null,
+      guestProfile: null,
+      ...tokenAccounts,
+      makerTokens: tokens,
+      associatedTokenProgram: mint ? ASSOCIATED_TOKEN_PROGRAM_ID : null,
+      systemProgram,
+    })
+    .rpc();
+
+  return { signature, board };
+}
+
 // --- 2. Guest joins board ---
 export async function joinBoard(
   program: Program<AnchorChess>,
@@ -50,10 +194,27 @@ export async function joinBoard(
   guest: web3.PublicKey,
   board: web3.PublicKey // PDA
 ) {
+  const { wagerMint, rated, inLobby } = await program.account.board.fetch(board);
+  const { tokens, ...tokenAccounts } = tokenWagerAccounts(wagerMint, guest, board);
+  const challenge = inLobby
+    ? await program.account.challenge.fetch(challengePda(program, board))
+    : null;
+
   try {
     const signature = await program.methods
       .join(guest)
-      .accountsStrict({ maker, board, guest })
+      .accountsStrict({
+        maker,
+        board,
+        guest,
+        guestProfile:
+          rated || challenge?.minProfileAge.gtn(0) ? profilePda(program, guest) : null,
+        challenge: inLobby ? challengePda(program, board) : null,
+        config: configPda(program),
+        ...tokenAccounts,
+        guestTokens: tokens,
+        systemProgram,
+      })
       .rpc();
 
     return { signature, board, successful: true };
@@ -83,12 +244,20 @@ export async function movePiece(
   maker: web3.PublicKey,
   board: web3.PublicKey,
   pieceIdx: number,
-  destination: number
+  destination: number,
+  // e.g. { queen: {} } when a pawn reaches the last rank
+  promotion: { queen: {} } | { rook: {} } | { bishop: {} } | { knight: {} } | null = null
 ) {
   try {
     const signature = await program.methods
-      .movePiece(pieceIdx, destination)
-      .accountsStrict({ player, board })
+      .movePiece(pieceIdx, destination, promotion)
+      .accountsStrict({
+        player,
+        board,
+        history: historyPda(program, board),
+        ...(await ratedProfiles(program, board)),
+        systemProgram,
+      })
       .rpc();
 
     return { signature, board, successful: true };
@@ -113,21 +282,284 @@ export async function resign(
 ) {
   const signature = await program.methods
     .resign()
+    .accountsStrict({ player, board, ...(await ratedProfiles(program, board)) })
+    .rpc();
+
+  return signature;
+}
+
+// --- 5. Claim a game the opponent abandoned or lost on time ---
+export async function claimTimeout(
+  program: Program<AnchorChess>,
+  player: web3.PublicKey,
+  board: web3.PublicKey
+) {
+  const signature = await program.methods
+    .claimTimeout()
+    .accountsStrict({ player, board, ...(await ratedProfiles(program, board)) })
+    .rpc();
+
+  return signature;
+}
+
+// --- 6. Draw offers ---
+export async function offerDraw(
+  program: Program<AnchorChess>,
+  player: web3.PublicKey,
+  board: web3.PublicKey
+) {
+  const signature = await program.methods
+    .offerDraw()
+    .accountsStrict({ player, board })
+    .rpc();
+
+  return signature;
+}
+
+export async function acceptDraw(
+  program: Program<AnchorChess>,
+  player: web3.PublicKey,
+  board: web3.PublicKey
+) {
+  const signature = await program.methods
+    .acceptDraw()
+    .accountsStrict({ player, board, ...(await ratedProfiles(program, board)) })
+    .rpc();
+
+  return signature;
+}
+
+export async function declineDraw(
+  program: Program<AnchorChess>,
+  player: web3.PublicKey,
+  board: web3.PublicKey
+) {
+  const signature = await program.methods
+    .declineDraw()
     .accountsStrict({ player, board })
     .rpc();
 
   return signature;
 }
 
-// --- 5. Close the board ---
+// --- 7. Claim a draw by threefold repetition or the fifty-move rule ---
+export async function claimDraw(
+  program: Program<AnchorChess>,
+  player: web3.PublicKey,
+  board: web3.PublicKey
+) {
+  const signature = await program.methods
+    .claimDraw()
+    .accountsStrict({
+      player,
+      board,
+      history: historyPda(program, board),
+      ...(await ratedProfiles(program, board)),
+    })
+    .rpc();
+
+  return signature;
+}
+
+// --- 8. Pay out the wagers of a finished game ---
+export async function settleWagers(
+  program: Program<AnchorChess>,
+  maker: web3.PublicKey,
+  // null when the guest never joined
+  guest: web3.PublicKey | null,
+  board: web3.PublicKey
+) {
+  const { wagerMint } = await program.account.board.fetch(board);
+  const makerAccounts = tokenWagerAccounts(wagerMint, maker, board);
+  const { tokens: guestTokens } = tokenWagerAccounts(wagerMint, guest, board);
+  const config = configPda(program);
+  const { treasury } = await program.account.config.fetch(config);
+  // The treasury's associated token account must exist to take fees in tokens
+  const { tokens: treasuryTokens } = tokenWagerAccounts(wagerMint, treasury, board);
+
+  const signature = await program.methods
+    .settle()
+    .accountsStrict({
+      board,
+      maker,
+      guest,
+      mint: makerAccounts.mint,
+      vault: makerAccounts.vault,
+      makerTokens: makerAccounts.tokens,
+      guestTokens,
+      config,
+      treasury,
+      treasuryTokens,
+      tokenProgram: makerAccounts.tokenProgram,
+    })
+    .rpc();
+
+  return signature;
+}
+
+// --- 9. Close the board ---
 export async function closeBoard(
   program: Program<AnchorChess>,
   maker: web3.PublicKey,
   board: web3.PublicKey
 ) {
+  const { wagerMint, inLobby } = await program.account.board.fetch(board);
+  // Tokens sent to the vault after the payout go back to the maker
+  const { mint, tokens: makerTokens, vault, tokenProgram } = tokenWagerAccounts(
+    wagerMint,
+    maker,
+    board
+  );
+
   const signature = await program.methods
     .close()
-    .accountsStrict({ maker, board })
+    .accountsStrict({
+      maker,
+      board,
+      history: historyPda(program, board),
+      challenge: inLobby ? challengePda(program, board) : null,
+      vault,
+      mint,
+      makerTokens,
+      tokenProgram,
+    })
+    .rpc();
+
+  return signature;
+}
+
+// --- 2b. Lobby ---
+// Lists a board waiting for its guest, optionally limited to some wallets
+// or to profiles older than `minProfileAge` seconds
+export async function openChallenge(
+  program: Program<AnchorChess>,
+  maker: web3.PublicKey,
+  board: web3.PublicKey,
+  allowedGuests: web3.PublicKey[] = [],
+  minProfileAge = 0
+) {
+  const signature = await program.methods
+    .openChallenge(allowedGuests, minProfileAge)
+    .accountsStrict({
+      maker,
+      board,
+      challenge: challengePda(program, board),
+      config: configPda(program),
+      systemProgram,
+    })
+    .rpc();
+
+  return signature;
+}
+
+export async function cancelChallenge(
+  program: Program<AnchorChess>,
+  maker: web3.PublicKey,
+  board: web3.PublicKey
+) {
+  const signature = await program.methods
+    .cancelChallenge()
+    .accountsStrict({ maker, board, challenge: challengePda(program, board) })
+    .rpc();
+
+  return signature;
+}
+
+// Open challenges `player` may take up: the allow-list admits them and,
+// when a minimum profile age is set, their profile is old enough
+export async function fetchLobby(program: Program<AnchorChess>, player: web3.PublicKey | null) {
+  const challenges = await program.account.challenge.all();
+  if (!player) return challenges;
+
+  const profile = await program.account.playerProfile.fetchNullable(
+    profilePda(program, player)
+  );
+  const now = Math.floor(Date.now() / 1000);
+  return challenges.filter(
+    ({ account }) =>
+      !account.maker.equals(player) &&
+      (account.allowedGuests.length === 0 ||
+        account.allowedGuests.some((allowed) => allowed.equals(player))) &&
+      (account.minProfileAge.isZero() ||
+        (profile !== null && profile.createdAt.add(account.minProfileAge).lten(now)))
+  );
+}
+
+// --- 10. Create the player's profile, needed for rated games ---
+export async function createProfile(program: Program<AnchorChess>, player: web3.PublicKey) {
+  const signature = await program.methods
+    .createProfile()
+    .accountsStrict({ player, profile: profilePda(program, player), systemProgram })
+    .rpc();
+
+  return signature;
+}
+
+// --- 11. Program settings, admin only ---
+// Once per deployment, by the program's upgrade authority
+export async function initializeConfig(
+  program: Program<AnchorChess>,
+  admin: web3.PublicKey,
+  // e.g. 250 for 2.5% of won pots
+  feeBps: number,
+  // must hold enough lamports to be rent exempt
+  treasury: web3.PublicKey
+) {
+  const [programData] = web3.PublicKey.findProgramAddressSync(
+    [program.programId.toBuffer()],
+    BPF_LOADER_UPGRADEABLE
+  );
+
+  const signature = await program.methods
+    .initializeConfig(feeBps)
+    .accountsStrict({
+      admin,
+      config: configPda(program),
+      program: program.programId,
+      programData,
+      treasury,
+      systemProgram,
+    })
+    .rpc();
+
+  return signature;
+}
+
+export async function updateConfig(
+  program: Program<AnchorChess>,
+  admin: web3.PublicKey,
+  feeBps: number,
+  treasury: web3.PublicKey
+) {
+  const signature = await program.methods
+    .updateConfig(feeBps)
+    .accountsStrict({ admin, config: configPda(program), treasury })
+    .rpc();
+
+  return signature;
+}
+
+export async function setPaused(
+  program: Program<AnchorChess>,
+  admin: web3.PublicKey,
+  paused: boolean
+) {
+  const signature = await program.methods
+    .setPaused(paused)
+    .accountsStrict({ admin, config: configPda(program) })
+    .rpc();
+
+  return signature;
+}
+
+export async function setAdmin(
+  program: Program<AnchorChess>,
+  admin: web3.PublicKey,
+  newAdmin: web3.PublicKey
+) {
+  const signature = await program.methods
+    .setAdmin(newAdmin)
+    .accountsStrict({ admin, config: configPda(program) })
     .rpc();
 
   return signature;
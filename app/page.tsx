@@ -25,7 +25,7 @@ export default function Page() {
             </div>
             {/* Right side: Board list */}
             <div className="flex-1 border p-4">
-              <h2 className="text-lg font-bold mb-2">All Boards</h2>
+              <h2 className="text-lg font-bold mb-2">Lobby</h2>
               <div className="overflow-auto max-h-[600px]">
                 <BoardsList />
               </div>
@@ -1,3 +1,5 @@
+use crate::config::fee_of;
+use crate::game_logic::{PieceType, Position, Square, ALL_CASTLING_RIGHTS, FIFTY_MOVE_RULE};
 use crate::ChessError;
 use anchor_lang::prelude::*;
 
@@ -10,16 +12,176 @@ pub struct Board {
     pub maker: Pubkey,
     pub guest: Option<Pubkey>,
     /// 1-based coordinate of pieces
-    /// the index will show WHICH piece it is
-    /// the number will tell the position.
+    /// the index will show WHICH piece it is (a `game_logic::PieceId`)
+    /// the number will tell the position (a `game_logic::Square`, `CAPTURED` once taken).
     /// First 16 are white, other 16 are black.
-    /// The whites are at the bottom, 1-index is the left tower.
+    /// The whites are at the bottom, index 0 is the left tower.
     pub state: [u8; 32],
-    pub game_over: bool,
+    /// Castling still available to each side, see `game_logic::WHITE_KINGSIDE` and friends
+    pub castling_rights: u8,
+    /// Square skipped by the last double pawn push, capturable en passant for one ply
+    pub en_passant: Option<u8>,
+    /// Piece each pawn slot (`state[8..24]`) was promoted to, if any
+    pub promotions: [Option<Promotion>; 16],
+    /// Plies since the last capture or pawn move
+    pub halfmove_clock: u16,
+    /// Starts at 1 and goes up after each black move
+    pub fullmove_number: u16,
+    /// Time control chosen by the maker, `None` for untimed games
+    pub time_control: Option<TimeControl>,
+    /// Seconds left on each clock, white first
+    pub time_left: [i64; 2],
+    /// Unix timestamp of the last move, or of the game start
+    pub last_move_ts: i64,
+    /// Seconds an untimed game may sit idle before the waiting player can claim it
+    pub inactivity_window: i64,
+    /// Player with a pending draw offer, withdrawn once they move again
+    pub draw_offer: Option<Pubkey>,
+    pub result: GameResult,
+    /// Why the game ended, set together with `result`
+    pub termination: Option<Termination>,
+    /// Amount each player stakes on the game, 0 for none
+    pub wager: u64,
+    /// Mint of a token wager, held in the board's associated token account.
+    /// `None` for wagers in lamports, held by the board itself.
+    pub wager_mint: Option<Pubkey>,
+    /// Stakes deposited so far and held by this account until `settle_wager`
+    pub pot: u64,
+    /// Protocol fee on a won pot, in basis points, from the `Config` of when the game was created
+    pub fee_bps: u16,
+    /// The result counts towards both players' `PlayerProfile` ratings
+    pub rated: bool,
+    /// Listed in the lobby by an open `Challenge`, which then decides who may join
+    pub in_lobby: bool,
+}
+
+/// Split of a settled pot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Payout {
+    pub maker: u64,
+    pub guest: u64,
+    /// Goes to the treasury
+    pub fee: u64,
+}
+
+/// Piece a pawn turns into on the last rank
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum Promotion {
+    Queen,
+    Rook,
+    Bishop,
+    Knight,
+}
+
+impl From<Promotion> for PieceType {
+    fn from(promotion: Promotion) -> Self {
+        match promotion {
+            Promotion::Queen => PieceType::Queen,
+            Promotion::Rook => PieceType::Rook,
+            Promotion::Bishop => PieceType::Bishop,
+            Promotion::Knight => PieceType::Knight,
+        }
+    }
+}
+
+impl TryFrom<PieceType> for Promotion {
+    type Error = ChessError;
+
+    fn try_from(piece: PieceType) -> std::result::Result<Self, Self::Error> {
+        match piece {
+            PieceType::Queen => Ok(Promotion::Queen),
+            PieceType::Rook => Ok(Promotion::Rook),
+            PieceType::Bishop => Ok(Promotion::Bishop),
+            PieceType::Knight => Ok(Promotion::Knight),
+            _ => Err(ChessError::InvalidPromotion),
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum TimeControl {
+    /// Base time plus an increment added after every move, in seconds
+    Fischer {
+        base_seconds: u32,
+        increment_seconds: u32,
+    },
+    /// Fixed number of days for each move
+    Correspondence { days_per_move: u16 },
+}
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Inactivity window used when the maker doesn't pick one
+pub const DEFAULT_INACTIVITY_WINDOW: i64 = 7 * SECONDS_PER_DAY;
+
+impl TimeControl {
+    /// Time on each clock when the game starts
+    fn initial_time(&self) -> i64 {
+        match *self {
+            TimeControl::Fischer { base_seconds, .. } => base_seconds as i64,
+            TimeControl::Correspondence { days_per_move } => days_per_move as i64 * SECONDS_PER_DAY,
+        }
+    }
+
+    /// Clock of the player who just moved, given what was left after thinking
+    fn after_move(&self, time_left: i64) -> i64 {
+        match *self {
+            TimeControl::Fischer {
+                increment_seconds, ..
+            } => time_left + increment_seconds as i64,
+            TimeControl::Correspondence { .. } => self.initial_time(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        require!(self.initial_time() > 0, ChessError::InvalidTimeControl);
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum GameResult {
+    Ongoing,
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl GameResult {
+    /// The result of a game won by the given colour
+    pub fn win_for(is_white: bool) -> Self {
+        if is_white {
+            GameResult::WhiteWins
+        } else {
+            GameResult::BlackWins
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum Termination {
+    Checkmate,
+    Resignation,
+    Timeout,
+    Stalemate,
+    Agreement,
+    Repetition,
+    FiftyMoves,
+    InsufficientMaterial,
+    Abandonment,
 }
 
 impl Board {
-    pub fn new(bump: u8, seed: u64, guest: Option<Pubkey>, maker: Pubkey) -> Self {
+    pub fn new(
+        bump: u8,
+        seed: u64,
+        guest: Option<Pubkey>,
+        maker: Pubkey,
+        time_control: Option<TimeControl>,
+        inactivity_window: i64,
+    ) -> Self {
+        let initial_time = time_control.map_or(0, |control| control.initial_time());
+
         Self {
             is_white_turn: true,
             bump,
@@ -27,42 +189,481 @@ impl Board {
             guest,
             maker,
             state: Self::new_chessboard(),
-            game_over: false,
+            castling_rights: ALL_CASTLING_RIGHTS,
+            en_passant: None,
+            promotions: [None; 16],
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            time_control,
+            time_left: [initial_time; 2],
+            last_move_ts: 0,
+            inactivity_window,
+            draw_offer: None,
+            result: GameResult::Ongoing,
+            termination: None,
+            wager: 0,
+            wager_mint: None,
+            pot: 0,
+            fee_bps: 0,
+            rated: false,
+            in_lobby: false,
         }
     }
 
+    /// Starts white's clock, once both players are seated
+    pub fn start_clock(&mut self, now: i64) {
+        self.last_move_ts = now;
+    }
+
+    /// Charges the time spent on this move to the player to move,
+    /// failing if their flag has already fallen.
+    /// Untimed games only note when the move was made, for the inactivity window.
+    pub fn punch_clock(&mut self, now: i64) -> Result<()> {
+        if let Some(time_control) = self.time_control {
+            let clock = if self.is_white_turn { 0 } else { 1 };
+            let time_left = self.time_left[clock] - (now - self.last_move_ts);
+            require!(time_left >= 0, ChessError::TimeExpired);
+
+            self.time_left[clock] = time_control.after_move(time_left);
+        }
+        self.last_move_ts = now;
+
+        Ok(())
+    }
+
+    /// Snapshot of the position for the rules engine
+    pub fn position(&self) -> Position {
+        Position {
+            state: self.state,
+            is_white_turn: self.is_white_turn,
+            castling_rights: self.castling_rights,
+            en_passant: self
+                .en_passant
+                .and_then(|square| Square::try_from(square).ok()),
+            promotions: self
+                .promotions
+                .map(|promotion| promotion.map(PieceType::from)),
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+        }
+    }
+
+    pub fn set_position(&mut self, position: Position) {
+        self.state = position.state;
+        self.is_white_turn = position.is_white_turn;
+        self.castling_rights = position.castling_rights;
+        self.en_passant = position.en_passant.map(u8::from);
+        self.promotions = position
+            .promotions
+            .map(|piece| piece.and_then(|piece| Promotion::try_from(piece).ok()));
+        self.halfmove_clock = position.halfmove_clock;
+        self.fullmove_number = position.fullmove_number;
+    }
+
+    /// Ends the game in favour of the opponent. The maker resigning before anyone
+    /// joined cancels the board: nobody can join any more and `settle_wager` refunds the stake.
     pub fn resign(&mut self, resigning_player: Pubkey) -> Result<()> {
         // Check that resigning player is one of the two
+        require!(self.is_player(resigning_player), ChessError::InvalidPlayer);
+        require!(!self.is_over(), ChessError::GameAlreadyOver);
+
+        // Maker plays white, so the other colour wins
+        let winner_is_white = resigning_player.ne(&self.maker);
+        self.finish(
+            GameResult::win_for(winner_is_white),
+            Termination::Resignation,
+        );
+
+        Ok(())
+    }
+
+    /// Ends the game in favour of the waiting player once the player to move
+    /// has run out of time, or for untimed games has been idle past the inactivity window
+    pub fn claim_timeout(&mut self, player: Pubkey, now: i64) -> Result<()> {
+        require!(self.is_player(player), ChessError::InvalidPlayer);
+        require!(self.guest.is_some(), ChessError::GuestPlayerNotPresent);
+        require!(!self.is_over(), ChessError::GameAlreadyOver);
+
+        // Only the player waiting for the opponent's move can claim
+        let claimant_is_white = player.eq(&self.maker);
         require!(
-            resigning_player.eq(&self.maker) || Some(resigning_player).eq(&self.guest),
+            claimant_is_white != self.is_white_turn,
             ChessError::InvalidPlayer
         );
 
-        self.game_over = true;
+        let idle = now - self.last_move_ts;
+        let termination = match self.time_control {
+            Some(_) => {
+                let clock = if self.is_white_turn { 0 } else { 1 };
+                require!(
+                    idle > self.time_left[clock],
+                    ChessError::OpponentStillHasTime
+                );
+                Termination::Timeout
+            }
+            None => {
+                require!(
+                    idle > self.inactivity_window,
+                    ChessError::OpponentStillHasTime
+                );
+                Termination::Abandonment
+            }
+        };
+
+        self.finish(GameResult::win_for(claimant_is_white), termination);
+
+        Ok(())
+    }
+
+    /// Offers a draw to the opponent.
+    /// Offers are withdrawn by the offering player's next move, so offer right after moving.
+    pub fn offer_draw(&mut self, player: Pubkey) -> Result<()> {
+        require!(self.is_player(player), ChessError::InvalidPlayer);
+        require!(self.guest.is_some(), ChessError::GuestPlayerNotPresent);
+        require!(!self.is_over(), ChessError::GameAlreadyOver);
+        require!(self.draw_offer.is_none(), ChessError::DrawAlreadyOffered);
+
+        self.draw_offer = Some(player);
+
+        Ok(())
+    }
+
+    /// Accepts the opponent's pending draw offer, ending the game
+    pub fn accept_draw(&mut self, player: Pubkey) -> Result<()> {
+        self.answer_draw_offer(player)?;
+        self.finish(GameResult::Draw, Termination::Agreement);
+
+        Ok(())
+    }
+
+    /// Declines the opponent's pending draw offer
+    pub fn decline_draw(&mut self, player: Pubkey) -> Result<()> {
+        self.answer_draw_offer(player)
+    }
+
+    /// Ends the game as a draw by threefold repetition or the fifty-move rule,
+    /// given how many times the current position has occurred (see `MoveHistory::repetitions`)
+    pub fn claim_draw(&mut self, player: Pubkey, repetitions: usize) -> Result<()> {
+        require!(self.is_player(player), ChessError::InvalidPlayer);
+        require!(self.guest.is_some(), ChessError::GuestPlayerNotPresent);
+        require!(!self.is_over(), ChessError::GameAlreadyOver);
+
+        let termination = if repetitions >= 3 {
+            Termination::Repetition
+        } else if self.halfmove_clock >= FIFTY_MOVE_RULE {
+            Termination::FiftyMoves
+        } else {
+            return err!(ChessError::NoDrawToClaim);
+        };
+
+        self.finish(GameResult::Draw, termination);
+
+        Ok(())
+    }
+
+    /// Clears a pending offer, which only the opponent of the offering player may answer
+    fn answer_draw_offer(&mut self, player: Pubkey) -> Result<()> {
+        require!(self.is_player(player), ChessError::InvalidPlayer);
+        require!(!self.is_over(), ChessError::GameAlreadyOver);
+        let offered_by = self.draw_offer.ok_or(ChessError::NoDrawOffer)?;
+        require!(offered_by != player, ChessError::InvalidPlayer);
+
+        self.draw_offer = None;
 
         Ok(())
     }
 
+    /// Adds a player's stake to the pot, once transferred to this account
+    pub fn deposit_wager(&mut self) {
+        self.pot += self.wager;
+    }
+
+    /// Empties the pot of a finished game: everything but the fee to the winner,
+    /// or each stake back on a draw or if nobody joined
+    pub fn settle_wager(&mut self) -> Result<Payout> {
+        require!(self.is_over(), ChessError::GameNotOver);
+
+        let pot = std::mem::take(&mut self.pot);
+        let fee = fee_of(pot, self.fee_bps);
+        let payout = match (self.result, self.guest) {
+            (GameResult::WhiteWins, Some(_)) => Payout {
+                maker: pot - fee,
+                guest: 0,
+                fee,
+            },
+            (GameResult::BlackWins, Some(_)) => Payout {
+                maker: 0,
+                guest: pot - fee,
+                fee,
+            },
+            _ => {
+                let maker = pot.min(self.wager);
+                Payout {
+                    maker,
+                    guest: pot - maker,
+                    fee: 0,
+                }
+            }
+        };
+
+        Ok(payout)
+    }
+
+    pub fn is_player(&self, player: Pubkey) -> bool {
+        player.eq(&self.maker) || Some(player).eq(&self.guest)
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.result != GameResult::Ongoing
+    }
+
+    /// Ends the game, recording the result and why it ended
+    pub fn finish(&mut self, result: GameResult, termination: Termination) {
+        self.draw_offer = None;
+        self.result = result;
+        self.termination = Some(termination);
+    }
+
     pub fn new_chessboard() -> [u8; 32] {
         let mut state = [0u8; 32];
 
         // White back rank: 1..=8
         for (i, v) in (1..=8).enumerate() {
-            state[i as usize] = v;
+            state[i] = v;
         }
         // White pawns: 9..=16
         for (i, v) in (9..=16).enumerate() {
-            state[8 + i as usize] = v;
+            state[8 + i] = v;
         }
         // Black pawns: 49..=56
         for (i, v) in (49..=56).enumerate() {
-            state[16 + i as usize] = v;
+            state[16 + i] = v;
         }
         // Black back rank: 57..=64
         for (i, v) in (57..=64).enumerate() {
-            state[24 + i as usize] = v;
+            state[24 + i] = v;
         }
 
         state
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_logic::fen;
+
+    fn timed_board(time_control: TimeControl) -> Board {
+        let mut board = Board::new(
+            255,
+            0,
+            Some(Pubkey::new_unique()),
+            Pubkey::new_unique(),
+            Some(time_control),
+            DEFAULT_INACTIVITY_WINDOW,
+        );
+        board.start_clock(1_000);
+        board
+    }
+
+    #[test]
+    fn test_new_board_is_start_position() {
+        let board = timed_board(TimeControl::Correspondence { days_per_move: 1 });
+        assert_eq!(board.position().to_fen(), fen::START_FEN);
+
+        let mut copy = board.clone();
+        copy.set_position(Position::from_fen(fen::START_FEN).unwrap());
+        assert_eq!(copy.state, board.state);
+        assert_eq!(copy.fullmove_number, 1);
+    }
+
+    #[test]
+    fn test_fischer_clock() {
+        // 3+2 blitz
+        let mut board = timed_board(TimeControl::Fischer {
+            base_seconds: 180,
+            increment_seconds: 2,
+        });
+
+        // White thinks 10 seconds and gets the increment back
+        board.punch_clock(1_010).unwrap();
+        assert_eq!(board.time_left, [172, 180]);
+        assert_eq!(board.last_move_ts, 1_010);
+
+        // Black runs out of time
+        board.is_white_turn = false;
+        assert!(board.punch_clock(1_191).is_err());
+        board.punch_clock(1_190).unwrap();
+        assert_eq!(board.time_left, [172, 2]);
+    }
+
+    #[test]
+    fn test_correspondence_clock() {
+        let mut board = timed_board(TimeControl::Correspondence { days_per_move: 3 });
+
+        // Each move gets the full allowance again
+        board.punch_clock(1_000 + 2 * SECONDS_PER_DAY).unwrap();
+        assert_eq!(board.time_left[0], 3 * SECONDS_PER_DAY);
+
+        board.is_white_turn = false;
+        let deadline = board.last_move_ts + 3 * SECONDS_PER_DAY;
+        assert!(board.punch_clock(deadline + 1).is_err());
+    }
+
+    #[test]
+    fn test_untimed_board_ignores_clock() {
+        let mut board = Board::new(
+            255,
+            0,
+            None,
+            Pubkey::new_unique(),
+            None,
+            DEFAULT_INACTIVITY_WINDOW,
+        );
+        board.punch_clock(i64::MAX).unwrap();
+        assert_eq!(board.time_left, [0, 0]);
+    }
+
+    #[test]
+    fn test_claim_timeout_on_flag_fall() {
+        let mut board = timed_board(TimeControl::Fischer {
+            base_seconds: 60,
+            increment_seconds: 0,
+        });
+        let white = board.maker;
+        let black = board.guest.unwrap();
+
+        // White to move: white can't claim against itself, black must wait for the flag
+        assert!(board.claim_timeout(white, 2_000).is_err());
+        assert!(board.claim_timeout(black, 1_060).is_err());
+
+        board.claim_timeout(black, 1_061).unwrap();
+        assert_eq!(board.result, GameResult::BlackWins);
+        assert_eq!(board.termination, Some(Termination::Timeout));
+        assert!(board.is_over());
+    }
+
+    #[test]
+    fn test_claim_draw_by_repetition() {
+        let mut board = timed_board(TimeControl::Correspondence { days_per_move: 1 });
+        let (white, black) = (board.maker, board.guest.unwrap());
+
+        assert!(board.claim_draw(Pubkey::new_unique(), 3).is_err());
+        assert!(board.claim_draw(white, 2).is_err());
+
+        // Either player may claim, whoever is to move
+        board.claim_draw(black, 3).unwrap();
+        assert_eq!(board.result, GameResult::Draw);
+        assert_eq!(board.termination, Some(Termination::Repetition));
+        assert!(board.claim_draw(white, 3).is_err());
+    }
+
+    #[test]
+    fn test_claim_draw_by_fifty_moves() {
+        let mut board = timed_board(TimeControl::Correspondence { days_per_move: 1 });
+
+        board.halfmove_clock = FIFTY_MOVE_RULE - 1;
+        assert!(board.claim_draw(board.maker, 1).is_err());
+
+        board.halfmove_clock = FIFTY_MOVE_RULE;
+        board.claim_draw(board.maker, 1).unwrap();
+        assert_eq!(board.result, GameResult::Draw);
+        assert_eq!(board.termination, Some(Termination::FiftyMoves));
+    }
+
+    fn wagered_board(wager: u64) -> Board {
+        let mut board = timed_board(TimeControl::Correspondence { days_per_move: 1 });
+        board.wager = wager;
+        board.deposit_wager();
+        board.deposit_wager();
+        board
+    }
+
+    fn payout(maker: u64, guest: u64, fee: u64) -> Payout {
+        Payout { maker, guest, fee }
+    }
+
+    #[test]
+    fn test_settle_wager() {
+        let mut board = wagered_board(1_000);
+        assert_eq!(board.pot, 2_000);
+        assert!(board.settle_wager().is_err());
+
+        board.resign(board.maker).unwrap();
+        assert_eq!(board.settle_wager().unwrap(), payout(0, 2_000, 0));
+        assert_eq!(board.pot, 0);
+        // Nothing left the second time
+        assert_eq!(board.settle_wager().unwrap(), payout(0, 0, 0));
+
+        let mut board = wagered_board(1_000);
+        board.resign(board.guest.unwrap()).unwrap();
+        assert_eq!(board.settle_wager().unwrap(), payout(2_000, 0, 0));
+
+        let mut board = wagered_board(1_000);
+        board.finish(GameResult::Draw, Termination::Agreement);
+        assert_eq!(board.settle_wager().unwrap(), payout(1_000, 1_000, 0));
+    }
+
+    #[test]
+    fn test_settle_wager_fee() {
+        // 2.5% of won pots
+        let mut board = wagered_board(1_000);
+        board.fee_bps = 250;
+        board.resign(board.guest.unwrap()).unwrap();
+        assert_eq!(board.settle_wager().unwrap(), payout(1_950, 0, 50));
+
+        // Draws are fee free
+        let mut board = wagered_board(1_000);
+        board.fee_bps = 250;
+        board.finish(GameResult::Draw, Termination::Repetition);
+        assert_eq!(board.settle_wager().unwrap(), payout(1_000, 1_000, 0));
+    }
+
+    #[test]
+    fn test_settle_wager_without_guest() {
+        // The maker gives up waiting for an opponent
+        let mut board = Board::new(255, 0, None, Pubkey::new_unique(), None, 100);
+        board.wager = 1_000;
+        board.deposit_wager();
+        board.resign(board.maker).unwrap();
+
+        assert_eq!(board.settle_wager().unwrap(), payout(1_000, 0, 0));
+    }
+
+    #[test]
+    fn test_claim_abandoned_untimed_game() {
+        let guest = Pubkey::new_unique();
+        let mut board = Board::new(255, 0, Some(guest), Pubkey::new_unique(), None, 100);
+        board.start_clock(1_000);
+        board.is_white_turn = false;
+
+        assert!(board.claim_timeout(board.maker, 1_100).is_err());
+        board.claim_timeout(board.maker, 1_101).unwrap();
+        assert_eq!(board.result, GameResult::WhiteWins);
+        assert_eq!(board.termination, Some(Termination::Abandonment));
+
+        // Nothing left to claim
+        assert!(board.claim_timeout(board.maker, 9_999).is_err());
+    }
+
+    #[test]
+    fn test_untimed_moves_restart_inactivity_window() {
+        let guest = Pubkey::new_unique();
+        let mut board = Board::new(255, 0, Some(guest), Pubkey::new_unique(), None, 100);
+        board.start_clock(1_000);
+
+        // Both sides keep moving every 60 seconds, well past the window since the start
+        for now in (1_060..=1_540).step_by(60) {
+            board.punch_clock(now).unwrap();
+            board.is_white_turn = !board.is_white_turn;
+        }
+        // White moved last at 1_540
+        assert!(!board.is_white_turn);
+        assert!(board.claim_timeout(board.maker, 1_541).is_err());
+        assert!(board.claim_timeout(board.maker, 1_640).is_err());
+
+        // Black has really been idle now
+        board.claim_timeout(board.maker, 1_641).unwrap();
+        assert_eq!(board.termination, Some(Termination::Abandonment));
+    }
+}
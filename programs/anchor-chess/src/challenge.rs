@@ -0,0 +1,149 @@
+use crate::board::{Board, TimeControl};
+use crate::ChessError;
+use anchor_lang::prelude::*;
+
+/// Most wallets a challenge can be limited to
+pub const MAX_ALLOWED_GUESTS: usize = 8;
+
+/// Open invitation to join a `Board`, listed in the lobby at the PDA `["challenge", board]`
+/// until someone joins or the maker cancels it.
+/// Repeats the board's settings so the lobby can be shown from challenges alone.
+#[derive(InitSpace)]
+#[account(discriminator = 5)]
+pub struct Challenge {
+    pub board: Pubkey,
+    pub maker: Pubkey,
+    pub bump: u8,
+    pub time_control: Option<TimeControl>,
+    pub wager: u64,
+    pub wager_mint: Option<Pubkey>,
+    pub rated: bool,
+    /// Only these wallets may join, anyone when empty
+    #[max_len(MAX_ALLOWED_GUESTS)]
+    pub allowed_guests: Vec<Pubkey>,
+    /// Seconds the joining player's `PlayerProfile` must have existed, keeping out fresh wallets.
+    /// 0 when no profile is needed.
+    pub min_profile_age: i64,
+    pub created_at: i64,
+}
+
+impl Challenge {
+    pub fn new(
+        bump: u8,
+        board_key: Pubkey,
+        board: &Board,
+        allowed_guests: Vec<Pubkey>,
+        min_profile_age: u32,
+        now: i64,
+    ) -> Result<Self> {
+        require!(board.guest.is_none(), ChessError::GuestAlreadyPresent);
+        require!(!board.is_over(), ChessError::GameAlreadyOver);
+        require!(
+            allowed_guests.len() <= MAX_ALLOWED_GUESTS,
+            ChessError::InvalidChallenge
+        );
+        require!(
+            !allowed_guests.contains(&board.maker),
+            ChessError::InvalidChallenge
+        );
+
+        Ok(Self {
+            board: board_key,
+            maker: board.maker,
+            bump,
+            time_control: board.time_control,
+            wager: board.wager,
+            wager_mint: board.wager_mint,
+            rated: board.rated,
+            allowed_guests,
+            min_profile_age: i64::from(min_profile_age),
+            created_at: now,
+        })
+    }
+
+    /// Checks that `guest` may take up the challenge,
+    /// given when their profile was created if they have one
+    pub fn admits(&self, guest: Pubkey, profile_created_at: Option<i64>, now: i64) -> Result<()> {
+        require!(
+            self.allowed_guests.is_empty() || self.allowed_guests.contains(&guest),
+            ChessError::NotAllowedToJoin
+        );
+        if self.min_profile_age > 0 {
+            let created_at = profile_created_at.ok_or(ChessError::ProfileMissing)?;
+            require!(
+                now - created_at >= self.min_profile_age,
+                ChessError::NotAllowedToJoin
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{GameResult, DEFAULT_INACTIVITY_WINDOW};
+
+    fn open_board() -> Board {
+        Board::new(
+            255,
+            7,
+            None,
+            Pubkey::new_unique(),
+            None,
+            DEFAULT_INACTIVITY_WINDOW,
+        )
+    }
+
+    #[test]
+    fn test_new_challenge() {
+        let board = open_board();
+        let friend = Pubkey::new_unique();
+        let challenge = Challenge::new(
+            255,
+            Pubkey::new_unique(),
+            &board,
+            vec![friend],
+            3_600,
+            1_000,
+        )
+        .unwrap();
+        assert_eq!(challenge.maker, board.maker);
+        assert_eq!(challenge.min_profile_age, 3_600);
+
+        let too_many = vec![friend; MAX_ALLOWED_GUESTS + 1];
+        assert!(Challenge::new(255, Pubkey::new_unique(), &board, too_many, 0, 0).is_err());
+        let with_maker = vec![board.maker];
+        assert!(Challenge::new(255, Pubkey::new_unique(), &board, with_maker, 0, 0).is_err());
+
+        let mut seated = open_board();
+        seated.guest = Some(friend);
+        assert!(Challenge::new(255, Pubkey::new_unique(), &seated, vec![], 0, 0).is_err());
+        let mut over = open_board();
+        over.result = GameResult::BlackWins;
+        assert!(Challenge::new(255, Pubkey::new_unique(), &over, vec![], 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_admits() {
+        let board = open_board();
+        let friend = Pubkey::new_unique();
+        let stranger = Pubkey::new_unique();
+
+        let open = Challenge::new(255, Pubkey::new_unique(), &board, vec![], 0, 0).unwrap();
+        assert!(open.admits(stranger, None, 0).is_ok());
+
+        let private =
+            Challenge::new(255, Pubkey::new_unique(), &board, vec![friend], 0, 0).unwrap();
+        assert!(private.admits(friend, None, 0).is_ok());
+        assert!(private.admits(stranger, None, 0).is_err());
+
+        // Profiles a day old
+        let seasoned =
+            Challenge::new(255, Pubkey::new_unique(), &board, vec![], 86_400, 0).unwrap();
+        assert!(seasoned.admits(stranger, Some(1_000), 87_400).is_ok());
+        assert!(seasoned.admits(stranger, Some(1_000), 87_399).is_err());
+        assert!(seasoned.admits(stranger, None, 87_400).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+use crate::ChessError;
+use anchor_lang::prelude::*;
+
+/// Basis points in a whole
+pub const BPS_DENOMINATOR: u64 = 10_000;
+/// Highest fee the admin may set, 10%
+pub const MAX_FEE_BPS: u16 = 1_000;
+
+/// Program-wide settings, a singleton at the PDA `["config"]`
+/// set up once by the program's upgrade authority
+#[derive(InitSpace)]
+#[account(discriminator = 3)]
+pub struct Config {
+    /// May update the settings below
+    pub admin: Pubkey,
+    pub bump: u8,
+    /// Cut of each won pot, in basis points. Boards keep the fee they were created with.
+    pub fee_bps: u16,
+    /// Receives the fees, lamports or tokens of the wager's mint
+    pub treasury: Pubkey,
+    /// Stops games from being created, listed in the lobby or joined; running ones carry on
+    pub paused: bool,
+}
+
+impl Config {
+    pub fn set_fee(&mut self, fee_bps: u16, treasury: Pubkey) -> Result<()> {
+        require!(fee_bps <= MAX_FEE_BPS, ChessError::InvalidFee);
+        self.fee_bps = fee_bps;
+        self.treasury = treasury;
+
+        Ok(())
+    }
+}
+
+/// Share of `amount` taken by a fee of `fee_bps`, rounded down
+pub fn fee_of(amount: u64, fee_bps: u16) -> u64 {
+    // Can't overflow: fee_bps is at most 10_000
+    (amount as u128 * fee_bps as u128 / BPS_DENOMINATOR as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_of() {
+        assert_eq!(fee_of(2_000_000, 250), 50_000);
+        assert_eq!(fee_of(2_000_000, 0), 0);
+        // Rounds in favour of the players
+        assert_eq!(fee_of(399, 250), 9);
+        assert_eq!(fee_of(u64::MAX, MAX_FEE_BPS), u64::MAX / 10);
+    }
+
+    #[test]
+    fn test_set_fee() {
+        let mut config = Config {
+            admin: Pubkey::new_unique(),
+            bump: 255,
+            fee_bps: 0,
+            treasury: Pubkey::default(),
+            paused: false,
+        };
+        let treasury = Pubkey::new_unique();
+
+        config.set_fee(MAX_FEE_BPS, treasury).unwrap();
+        assert_eq!((config.fee_bps, config.treasury), (MAX_FEE_BPS, treasury));
+        assert!(config.set_fee(MAX_FEE_BPS + 1, treasury).is_err());
+    }
+}
@@ -22,4 +22,56 @@ pub enum ChessError {
     GuestAlreadyPresent,
     #[msg("Cannot close the match.")]
     CannotCloseMatch,
+    #[msg("Move would leave the king in check.")]
+    KingInCheck,
+    #[msg("The game is already over.")]
+    GameAlreadyOver,
+    #[msg("A pawn reaching the last rank must be promoted.")]
+    PromotionRequired,
+    #[msg("Invalid promotion")]
+    InvalidPromotion,
+    #[msg("A draw offer is already pending.")]
+    DrawAlreadyOffered,
+    #[msg("No draw offer to answer.")]
+    NoDrawOffer,
+    #[msg("Invalid time control")]
+    InvalidTimeControl,
+    #[msg("Player ran out of time.")]
+    TimeExpired,
+    #[msg("The opponent still has time to move.")]
+    OpponentStillHasTime,
+    #[msg("Invalid FEN position")]
+    InvalidFen,
+    #[msg("Position can't start a game.")]
+    InvalidSetup,
+    #[msg("Invalid move notation")]
+    InvalidNotation,
+    #[msg("No draw can be claimed.")]
+    NoDrawToClaim,
+    #[msg("Wagered games need the guest to join and deposit.")]
+    InvalidWager,
+    #[msg("The game is still running.")]
+    GameNotOver,
+    #[msg("The wagers must be paid out first.")]
+    WagerNotSettled,
+    #[msg("Token wager accounts are missing.")]
+    TokenAccountsMissing,
+    #[msg("Fee is above the maximum.")]
+    InvalidFee,
+    #[msg("Games are paused.")]
+    Paused,
+    #[msg("Only the admin can do this.")]
+    Unauthorized,
+    #[msg("Rated games need both players' profiles.")]
+    ProfileMissing,
+    #[msg("Invalid challenge settings.")]
+    InvalidChallenge,
+    #[msg("The challenge doesn't admit this player.")]
+    NotAllowedToJoin,
+    #[msg("The board's challenge is missing.")]
+    ChallengeMissing,
+    #[msg("Mints with transfer fees can't be wagered.")]
+    TransferFeeMint,
+    #[msg("The treasury must hold enough lamports to be rent exempt.")]
+    TreasuryNotRentExempt,
 }
@@ -0,0 +1,99 @@
+//! Events emitted by the instructions, for indexers and the frontend to follow games
+//! without polling `Board` accounts
+use crate::board::{GameResult, Promotion, Termination, TimeControl};
+use anchor_lang::prelude::*;
+
+#[event]
+pub struct GameCreated {
+    pub board: Pubkey,
+    pub maker: Pubkey,
+    pub guest: Option<Pubkey>,
+    pub seed: u64,
+    pub time_control: Option<TimeControl>,
+    /// Amount each player stakes
+    pub wager: u64,
+    /// Mint of a token wager, `None` for lamports
+    pub wager_mint: Option<Pubkey>,
+    /// FEN of the starting position
+    pub start_fen: String,
+}
+
+#[event]
+pub struct ChallengeOpened {
+    pub board: Pubkey,
+    pub maker: Pubkey,
+    /// Only these wallets may join, anyone when empty
+    pub allowed_guests: Vec<Pubkey>,
+}
+
+#[event]
+pub struct ChallengeClosed {
+    pub board: Pubkey,
+    /// The player who took it up, `None` when cancelled
+    pub guest: Option<Pubkey>,
+}
+
+#[event]
+pub struct GuestJoined {
+    pub board: Pubkey,
+    pub guest: Pubkey,
+}
+
+#[event]
+pub struct MoveMade {
+    pub board: Pubkey,
+    pub player: Pubkey,
+    /// Slot of the moved piece in `Board::state`
+    pub piece: u8,
+    pub from: u8,
+    pub to: u8,
+    pub promotion: Option<Promotion>,
+    pub capture: bool,
+    /// The opponent's king is attacked after the move
+    pub check: bool,
+}
+
+#[event]
+pub struct DrawOffered {
+    pub board: Pubkey,
+    pub player: Pubkey,
+}
+
+#[event]
+pub struct GameEnded {
+    pub board: Pubkey,
+    pub result: GameResult,
+    pub termination: Option<Termination>,
+}
+
+#[event]
+pub struct RatingsUpdated {
+    pub board: Pubkey,
+    /// New ratings of both players
+    pub white_rating: u16,
+    pub black_rating: u16,
+}
+
+#[event]
+pub struct WagerSettled {
+    pub board: Pubkey,
+    /// Amount paid out to each player
+    pub maker_share: u64,
+    pub guest_share: u64,
+    /// Amount paid to the treasury
+    pub fee: u64,
+}
+
+#[event]
+pub struct ConfigUpdated {
+    pub admin: Pubkey,
+    pub fee_bps: u16,
+    pub treasury: Pubkey,
+    pub paused: bool,
+}
+
+#[event]
+pub struct BoardClosed {
+    pub board: Pubkey,
+    pub maker: Pubkey,
+}
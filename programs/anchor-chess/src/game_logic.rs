@@ -3,35 +3,216 @@
 /// It should be a drop in replacement to any Rust program.
 use crate::ChessError;
 use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+pub mod fen;
+pub mod notation;
+mod zobrist;
+
+/// Everything the rules need to know about a game.
+/// Mirrors the relevant fields of the on-chain `Board`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Position {
+    /// Square of each piece slot, `CAPTURED` once taken
+    pub state: [u8; 32],
+    pub is_white_turn: bool,
+    /// Bit set of `WHITE_KINGSIDE`, `WHITE_QUEENSIDE`, `BLACK_KINGSIDE`, `BLACK_QUEENSIDE`
+    pub castling_rights: u8,
+    /// Square skipped by a pawn's double step on the previous ply
+    pub en_passant: Option<Square>,
+    /// What each pawn slot (8..24) has been promoted to, if anything
+    pub promotions: [Option<PieceType>; 16],
+    /// Plies since the last capture or pawn move
+    pub halfmove_clock: u16,
+    /// Starts at 1 and goes up after each black move
+    pub fullmove_number: u16,
+}
+
+impl Position {
+    /// Type of the piece in a slot, honouring promotions
+    pub fn piece_type(&self, piece: PieceId) -> PieceType {
+        match PieceType::from(piece) {
+            PieceType::Pawn => self.promotions[piece.index() - 8].unwrap_or(PieceType::Pawn),
+            piece_type => piece_type,
+        }
+    }
+
+    /// Where a piece stands, `None` once captured
+    pub fn square_of(&self, piece: PieceId) -> Option<Square> {
+        Square::try_from(self.state[piece.index()]).ok()
+    }
+}
+
+/// A square of the board, numbered like the board state: a1 = 1, h1 = 8, a8 = 57, h8 = 64
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Square(u8);
+
+impl Square {
+    /// Square on file `x` and rank `y`, both 0-based
+    pub fn from_coords(x: i8, y: i8) -> Option<Self> {
+        on_board(x, y).then(|| Square(to_square(x, y)))
+    }
 
+    /// File and rank, both 0-based
+    pub fn coords(self) -> (i8, i8) {
+        to_coords(self.0)
+    }
+}
+
+impl TryFrom<u8> for Square {
+    type Error = ChessError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1..=64 => Ok(Square(value)),
+            _ => Err(ChessError::OutOfBounds),
+        }
+    }
+}
+
+impl From<Square> for u8 {
+    fn from(square: Square) -> Self {
+        square.0
+    }
+}
+
+/// Algebraic name of the square, e.g. `e4`
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (x, y) = self.coords();
+        write!(f, "{}{}", (b'a' + x as u8) as char, y + 1)
+    }
+}
+
+impl FromStr for Square {
+    type Err = ChessError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.as_bytes() {
+            &[file @ b'a'..=b'h', rank @ b'1'..=b'8'] => {
+                Ok(Square(to_square((file - b'a') as i8, (rank - b'1') as i8)))
+            }
+            _ => Err(ChessError::OutOfBounds),
+        }
+    }
+}
+
+/// One of the 32 piece slots of the board state, laid out like `Board::new_chessboard`:
+/// 0..16 are white (back rank, then pawns), 16..32 black (pawns, then back rank)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PieceId(u8);
+
+impl PieceId {
+    pub fn is_white(self) -> bool {
+        self.0 < 16
+    }
+
+    /// Position of the slot in the board state
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    /// Every slot of one side
+    fn all(is_white: bool) -> impl Iterator<Item = PieceId> {
+        side_slots(is_white).map(|idx| PieceId(idx as u8))
+    }
+}
+
+impl TryFrom<u8> for PieceId {
+    type Error = ChessError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0..=31 => Ok(PieceId(value)),
+            _ => Err(ChessError::OutOfBounds),
+        }
+    }
+}
+
+impl From<PieceId> for u8 {
+    fn from(piece: PieceId) -> Self {
+        piece.0
+    }
+}
+
+/// A move as `move_piece` takes it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Move {
+    pub piece: PieceId,
+    pub destination: Square,
+    pub promotion: Option<PieceType>,
+}
+
+impl Move {
+    /// Packs the move into 14 bits: piece slot (5), destination square (6)
+    /// and promotion piece (3, 0 for none)
+    pub fn encode(&self) -> u16 {
+        let promotion = match self.promotion {
+            None => 0,
+            Some(piece) => PROMOTION_PIECES
+                .iter()
+                .position(|&promotable| promotable == piece)
+                .map_or(0, |idx| idx + 1) as u16,
+        };
+
+        (self.piece.0 as u16) << 9 | (self.destination.0 as u16 - 1) << 3 | promotion
+    }
+
+    /// Inverse of `encode`
+    pub fn decode(encoded: u16) -> Result<Self, ChessError> {
+        let promotion = match encoded & 0b111 {
+            0 => None,
+            code @ 1..=4 => Some(PROMOTION_PIECES[code as usize - 1]),
+            _ => return Err(ChessError::InvalidPromotion),
+        };
+
+        Ok(Move {
+            piece: PieceId::try_from((encoded >> 9) as u8)?,
+            destination: Square::try_from((encoded >> 3 & 0b11_1111) as u8 + 1)?,
+            promotion,
+        })
+    }
+}
+
+/// Whether the piece may go to `destination` following its own pattern,
+/// castling and en passant included. King safety is checked by `leaves_king_in_check`.
 pub fn is_move_legal(
-    current_pos: u8,
-    piece_idx: u8,
-    destination: u8,
-    board_state: &[u8; 32],
+    position: &Position,
+    piece: PieceId,
+    destination: Square,
 ) -> Result<bool, ChessError> {
-    let piece = PieceType::try_from(piece_idx)?;
+    let current = position.square_of(piece).ok_or(ChessError::InvalidPiece)?;
 
-    if destination == current_pos {
+    if destination == current {
         return Err(ChessError::NoMovement);
     }
 
-    let is_white = piece_idx < 16; // white = first 16 pieces
+    let board_state = &position.state;
+    let is_white = piece.is_white();
+    let (from, to) = (current.0, destination.0);
 
-    let legal = match piece {
-        PieceType::Pawn => is_pawn_move(current_pos, destination, is_white, board_state),
-        PieceType::Rook => is_rook_move(current_pos, destination, is_white, board_state),
-        PieceType::Knight => is_knight_move(current_pos, destination, is_white, board_state),
-        PieceType::Bishop => is_bishop_move(current_pos, destination, is_white, board_state),
-        PieceType::Queen => is_queen_move(current_pos, destination, is_white, board_state),
-        PieceType::King => is_king_move(current_pos, destination, is_white, board_state),
+    let legal = match position.piece_type(piece) {
+        PieceType::Pawn => {
+            is_pawn_move(from, to, is_white, board_state)
+                || is_en_passant_move(position, piece, destination)
+        }
+        PieceType::Rook => is_rook_move(from, to, is_white, board_state),
+        PieceType::Knight => is_knight_move(from, to, is_white, board_state),
+        PieceType::Bishop => is_bishop_move(from, to, is_white, board_state),
+        PieceType::Queen => is_queen_move(from, to, is_white, board_state),
+        PieceType::King => {
+            is_king_move(from, to, is_white, board_state)
+                || is_castling_move(position, piece, destination)
+        }
     };
 
     Ok(legal)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum PieceType {
+pub enum PieceType {
     Rook,
     Knight,
     Bishop,
@@ -40,42 +221,660 @@ enum PieceType {
     Pawn,
 }
 
-impl TryFrom<u8> for PieceType {
-    type Error = ChessError;
-
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
+impl From<PieceId> for PieceType {
+    /// Type a slot starts the game with, before any promotion
+    fn from(piece: PieceId) -> Self {
+        match piece.0 {
             // Rooks
-            1 | 8 | 25 | 32 => Ok(PieceType::Rook),
+            0 | 7 | 24 | 31 => PieceType::Rook,
             // Knights
-            2 | 7 | 26 | 31 => Ok(PieceType::Knight),
+            1 | 6 | 25 | 30 => PieceType::Knight,
             // Bishops
-            3 | 6 | 27 | 30 => Ok(PieceType::Bishop),
+            2 | 5 | 26 | 29 => PieceType::Bishop,
             // Queens
-            4 | 28 => Ok(PieceType::Queen),
+            3 | 27 => PieceType::Queen,
             // Kings
-            5 | 29 => Ok(PieceType::King),
+            4 | 28 => PieceType::King,
             // Pawns
-            9..=24 => Ok(PieceType::Pawn),
-            //
-            _ => Err(ChessError::InvalidPiece),
+            _ => PieceType::Pawn,
         }
     }
 }
 
 impl PieceType {
-    /// Returns all 1-based indices for this piece type
-    #[allow(dead_code)]
-    pub fn indices(&self) -> Vec<u8> {
+    /// Upper case letter of the piece in FEN and SAN
+    pub fn letter(&self) -> char {
         match self {
-            PieceType::Rook => vec![1, 8, 25, 32],
-            PieceType::Knight => vec![2, 7, 26, 31],
-            PieceType::Bishop => vec![3, 6, 27, 30],
-            PieceType::Queen => vec![4, 28],
-            PieceType::King => vec![5, 29],
-            PieceType::Pawn => (9..=16).chain(17..=24).collect(),
+            PieceType::Rook => 'R',
+            PieceType::Knight => 'N',
+            PieceType::Bishop => 'B',
+            PieceType::Queen => 'Q',
+            PieceType::King => 'K',
+            PieceType::Pawn => 'P',
+        }
+    }
+
+    /// Inverse of `letter`, either case
+    pub fn from_letter(letter: char) -> Option<Self> {
+        match letter.to_ascii_uppercase() {
+            'R' => Some(PieceType::Rook),
+            'N' => Some(PieceType::Knight),
+            'B' => Some(PieceType::Bishop),
+            'Q' => Some(PieceType::Queen),
+            'K' => Some(PieceType::King),
+            'P' => Some(PieceType::Pawn),
+            _ => None,
+        }
+    }
+
+    /// Returns all slots starting the game with this piece type
+    pub fn indices(&self) -> Vec<PieceId> {
+        let slots: Vec<u8> = match self {
+            PieceType::Rook => vec![0, 7, 24, 31],
+            PieceType::Knight => vec![1, 6, 25, 30],
+            PieceType::Bishop => vec![2, 5, 26, 29],
+            PieceType::Queen => vec![3, 27],
+            PieceType::King => vec![4, 28],
+            PieceType::Pawn => (8..=15).chain(16..=23).collect(),
+        };
+        slots.into_iter().map(PieceId).collect()
+    }
+}
+
+/// Value stored in the board state for a piece that has been captured
+pub const CAPTURED: u8 = 0;
+
+/// Slots of the white and black kings
+const WHITE_KING_IDX: usize = 4;
+const BLACK_KING_IDX: usize = 28;
+
+const ORTHOGONAL: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const DIAGONAL: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+const KNIGHT_JUMPS: [(i8, i8); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const KING_STEPS: [(i8, i8); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+/// Castling rights, one bit per king/rook pair that has not moved yet
+pub const WHITE_KINGSIDE: u8 = 1 << 0;
+pub const WHITE_QUEENSIDE: u8 = 1 << 1;
+pub const BLACK_KINGSIDE: u8 = 1 << 2;
+pub const BLACK_QUEENSIDE: u8 = 1 << 3;
+pub const ALL_CASTLING_RIGHTS: u8 =
+    WHITE_KINGSIDE | WHITE_QUEENSIDE | BLACK_KINGSIDE | BLACK_QUEENSIDE;
+
+/// A castling move, in the square numbering of `Board::new_chessboard`
+struct Castling {
+    right: u8,
+    king_idx: usize,
+    king_from: u8,
+    king_to: u8,
+    rook_idx: usize,
+    rook_from: u8,
+    rook_to: u8,
+    /// Squares between king and rook, must be empty
+    between: &'static [u8],
+    /// Squares the king stands on or crosses, must not be attacked
+    king_path: &'static [u8],
+}
+
+const CASTLINGS: [Castling; 4] = [
+    Castling {
+        right: WHITE_KINGSIDE,
+        king_idx: WHITE_KING_IDX,
+        king_from: 5,
+        king_to: 7,
+        rook_idx: 7,
+        rook_from: 8,
+        rook_to: 6,
+        between: &[6, 7],
+        king_path: &[5, 6, 7],
+    },
+    Castling {
+        right: WHITE_QUEENSIDE,
+        king_idx: WHITE_KING_IDX,
+        king_from: 5,
+        king_to: 3,
+        rook_idx: 0,
+        rook_from: 1,
+        rook_to: 4,
+        between: &[2, 3, 4],
+        king_path: &[5, 4, 3],
+    },
+    Castling {
+        right: BLACK_KINGSIDE,
+        king_idx: BLACK_KING_IDX,
+        king_from: 61,
+        king_to: 63,
+        rook_idx: 31,
+        rook_from: 64,
+        rook_to: 62,
+        between: &[62, 63],
+        king_path: &[61, 62, 63],
+    },
+    Castling {
+        right: BLACK_QUEENSIDE,
+        king_idx: BLACK_KING_IDX,
+        king_from: 61,
+        king_to: 59,
+        rook_idx: 24,
+        rook_from: 57,
+        rook_to: 60,
+        between: &[58, 59, 60],
+        king_path: &[61, 60, 59],
+    },
+];
+
+/// Finds the castling a king move corresponds to, if any
+fn find_castling(piece: PieceId, from: u8, destination: Square) -> Option<&'static Castling> {
+    CASTLINGS.iter().find(|castling| {
+        castling.king_idx == piece.index()
+            && castling.king_from == from
+            && castling.king_to == destination.0
+    })
+}
+
+/// Castling is legal while the right is kept, the rook is home,
+/// the squares in between are empty and the king doesn't start, cross or land in check
+fn is_castling_move(position: &Position, piece: PieceId, destination: Square) -> bool {
+    let board_state = &position.state;
+    let current_pos = board_state[piece.index()];
+    let Some(castling) = find_castling(piece, current_pos, destination) else {
+        return false;
+    };
+
+    let by_white = castling.king_idx != WHITE_KING_IDX;
+    position.castling_rights & castling.right != 0
+        && board_state[castling.rook_idx] == castling.rook_from
+        && !castling
+            .between
+            .iter()
+            .any(|square| board_state.contains(square))
+        && !castling
+            .king_path
+            .iter()
+            .any(|&square| is_square_attacked(Square(square), by_white, position))
+}
+
+/// A pawn may capture onto the square skipped by an enemy double step,
+/// but only on the ply right after it
+fn is_en_passant_move(position: &Position, piece: PieceId, destination: Square) -> bool {
+    if position.en_passant != Some(destination) {
+        return false;
+    }
+
+    let (cx, cy) = to_coords(position.state[piece.index()]);
+    let (dx, dy) = destination.coords();
+    let dir = if piece.is_white() { 1 } else { -1 };
+
+    (dx - cx).abs() == 1 && dy - cy == dir
+}
+
+/// Square of the pawn taken by an en passant capture landing on `en_passant`
+fn en_passant_victim(en_passant: Square, is_white: bool) -> Square {
+    if is_white {
+        Square(en_passant.0 - 8)
+    } else {
+        Square(en_passant.0 + 8)
+    }
+}
+
+/// Bitmap of every square attacked by one side.
+/// Bit `y * 8 + x` is set when the square at `(x, y)` is attacked.
+pub fn attack_map(by_white: bool, position: &Position) -> u64 {
+    let occupied = occupancy(&position.state, 0..32);
+
+    PieceId::all(by_white).fold(0, |map, piece| match position.square_of(piece) {
+        Some(square) => {
+            map | piece_attacks(position.piece_type(piece), square.0, by_white, occupied)
+        }
+        None => map,
+    })
+}
+
+/// Looks outward from the square instead of building the whole `attack_map`:
+/// a piece attacks it exactly when the same piece standing on it would attack back.
+pub fn is_square_attacked(square: Square, by_white: bool, position: &Position) -> bool {
+    let occupied = occupancy(&position.state, 0..32);
+    let target = square.coords();
+
+    // Attackers grouped by the way they move
+    let (mut pawns, mut knights, mut kings, mut straight, mut diagonal) = (0, 0, 0, 0, 0);
+    for piece in PieceId::all(by_white) {
+        let Some(origin) = position.square_of(piece) else {
+            continue;
+        };
+        let bit = square_bit(origin.0);
+        match position.piece_type(piece) {
+            PieceType::Pawn => pawns |= bit,
+            PieceType::Knight => knights |= bit,
+            PieceType::King => kings |= bit,
+            PieceType::Rook => straight |= bit,
+            PieceType::Bishop => diagonal |= bit,
+            PieceType::Queen => {
+                straight |= bit;
+                diagonal |= bit;
+            }
         }
     }
+
+    // Pawns capture forward, so look backwards from the target
+    let behind = if by_white { -1 } else { 1 };
+
+    step_attacks(target, &[(-1, behind), (1, behind)]) & pawns != 0
+        || step_attacks(target, &KNIGHT_JUMPS) & knights != 0
+        || step_attacks(target, &KING_STEPS) & kings != 0
+        || straight != 0 && ray_attacks(target, &ORTHOGONAL, occupied) & straight != 0
+        || diagonal != 0 && ray_attacks(target, &DIAGONAL, occupied) & diagonal != 0
+}
+
+/// Whether the king of the given colour is currently attacked
+pub fn is_in_check(is_white: bool, position: &Position) -> bool {
+    let king = if is_white {
+        WHITE_KING_IDX
+    } else {
+        BLACK_KING_IDX
+    };
+
+    position
+        .square_of(PieceId(king as u8))
+        .is_some_and(|square| is_square_attacked(square, !is_white, position))
+}
+
+/// Plays the move on a copy of the position and checks whether the mover's king
+/// ends up attacked. The move itself is assumed to follow the piece's pattern.
+pub fn leaves_king_in_check(position: &Position, piece: PieceId, destination: Square) -> bool {
+    let mut next = *position;
+    apply_move(&mut next, piece, destination, None);
+
+    is_in_check(piece.is_white(), &next)
+}
+
+/// Every legal move of the side to move, castling, en passant and
+/// one move per promotion piece included
+pub fn generate_legal_moves(position: &Position) -> Vec<Move> {
+    generate_pseudo_legal_moves(position)
+        .into_iter()
+        .filter(|mv| !leaves_king_in_check(position, mv.piece, mv.destination))
+        .collect()
+}
+
+/// Whether the side to move can make at least one move that doesn't leave its king attacked.
+/// Used after every move to detect checkmate and stalemate.
+pub fn has_legal_move(position: &Position) -> bool {
+    generate_pseudo_legal_moves(position)
+        .into_iter()
+        .any(|mv| !leaves_king_in_check(position, mv.piece, mv.destination))
+}
+
+/// Moves following each piece's pattern, without looking at the mover's king safety
+/// (castling aside, which already requires a safe path)
+fn generate_pseudo_legal_moves(position: &Position) -> Vec<Move> {
+    let board_state = &position.state;
+    let is_white = position.is_white_turn;
+    let own = occupancy(board_state, side_slots(is_white));
+    let enemies = occupancy(board_state, side_slots(!is_white));
+    let occupied = own | enemies;
+    let mut moves = Vec::new();
+
+    for piece in PieceId::all(is_white) {
+        let Some(origin) = position.square_of(piece) else {
+            continue;
+        };
+        let piece_type = position.piece_type(piece);
+
+        if piece_type == PieceType::Pawn {
+            let en_passant = position.en_passant.map_or(0, |square| square_bit(square.0));
+            let targets = pawn_pushes(origin.0, is_white, occupied)
+                | piece_attacks(piece_type, origin.0, is_white, occupied) & (enemies | en_passant);
+            let last_rank = if is_white { 7 } else { 0 };
+
+            for destination in squares(targets) {
+                if destination.coords().1 == last_rank {
+                    moves.extend(PROMOTION_PIECES.iter().map(|&promotion| Move {
+                        piece,
+                        destination,
+                        promotion: Some(promotion),
+                    }));
+                } else {
+                    moves.push(Move {
+                        piece,
+                        destination,
+                        promotion: None,
+                    });
+                }
+            }
+            continue;
+        }
+
+        let targets = piece_attacks(piece_type, origin.0, is_white, occupied) & !own;
+        moves.extend(squares(targets).map(|destination| Move {
+            piece,
+            destination,
+            promotion: None,
+        }));
+
+        if piece_type == PieceType::King {
+            moves.extend(
+                CASTLINGS
+                    .iter()
+                    .map(|castling| Square(castling.king_to))
+                    .filter(|&destination| is_castling_move(position, piece, destination))
+                    .map(|destination| Move {
+                        piece,
+                        destination,
+                        promotion: None,
+                    }),
+            );
+        }
+    }
+
+    moves
+}
+
+/// Plies without a capture or pawn move after which either player may claim a draw
+pub const FIFTY_MOVE_RULE: u16 = 100;
+/// Plies without a capture or pawn move after which the game is drawn
+pub const SEVENTY_FIVE_MOVE_RULE: u16 = 150;
+
+/// Whether neither side could ever mate: only kings and bishops all on squares
+/// of one colour (K vs K, K+B vs K, ...), or kings and a single knight
+pub fn is_insufficient_material(position: &Position) -> bool {
+    let mut knights = 0;
+    // Light and dark squared bishops seen
+    let mut bishops = [false; 2];
+
+    for piece in PieceId::all(true).chain(PieceId::all(false)) {
+        let Some(square) = position.square_of(piece) else {
+            continue;
+        };
+        match position.piece_type(piece) {
+            PieceType::King => {}
+            PieceType::Knight => knights += 1,
+            PieceType::Bishop => {
+                let (x, y) = square.coords();
+                bishops[((x + y) % 2) as usize] = true;
+            }
+            _ => return false,
+        }
+    }
+
+    match knights {
+        0 => !(bishops[0] && bishops[1]),
+        1 => bishops == [false; 2],
+        _ => false,
+    }
+}
+
+/// A position a game can start from: both kings on the board, no pawns on the
+/// first or last rank, castling rights and en passant square backed by the board,
+/// the side that just moved not in check, a move left to play,
+/// enough material left for a mate and the 75-move rule not already reached
+pub fn validate_setup(position: &Position) -> Result<(), ChessError> {
+    let kings_present = [WHITE_KING_IDX, BLACK_KING_IDX]
+        .iter()
+        .all(|&king| position.square_of(PieceId(king as u8)).is_some());
+
+    let pawn_on_back_rank = PieceId::all(true).chain(PieceId::all(false)).any(|piece| {
+        position.piece_type(piece) == PieceType::Pawn
+            && position
+                .square_of(piece)
+                .is_some_and(|square| matches!(square.coords().1, 0 | 7))
+    });
+
+    // Each castling right needs its king and rook still on their home squares
+    let phantom_castling = CASTLINGS.iter().any(|castling| {
+        position.castling_rights & castling.right != 0
+            && (position.state[castling.king_idx] != castling.king_from
+                || position.state[castling.rook_idx] != castling.rook_from)
+    });
+
+    // The double step needs an enemy pawn just past the target, with the target
+    // and the square the pawn came from both empty
+    let phantom_en_passant = position.en_passant.is_some_and(|target| {
+        let pawn_square = en_passant_victim(target, position.is_white_turn);
+        let origin = en_passant_victim(target, !position.is_white_turn);
+        let pawn_there = PieceId::all(!position.is_white_turn).any(|piece| {
+            position.state[piece.index()] == pawn_square.0
+                && position.piece_type(piece) == PieceType::Pawn
+        });
+
+        !pawn_there || position.state.contains(&target.0) || position.state.contains(&origin.0)
+    });
+
+    if !kings_present
+        || pawn_on_back_rank
+        || phantom_castling
+        || phantom_en_passant
+        || is_in_check(!position.is_white_turn, position)
+        || !has_legal_move(position)
+        || is_insufficient_material(position)
+        || position.halfmove_clock >= SEVENTY_FIVE_MOVE_RULE
+    {
+        return Err(ChessError::InvalidSetup);
+    }
+
+    Ok(())
+}
+
+/// Pieces a pawn may promote to
+const PROMOTION_PIECES: [PieceType; 4] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+];
+
+/// Non-capturing pawn moves: one step, or two from the starting rank
+fn pawn_pushes(origin: u8, is_white: bool, occupied: u64) -> u64 {
+    let (x, y) = to_coords(origin);
+    let dir = if is_white { 1 } else { -1 };
+    let start_rank = if is_white { 1 } else { 6 };
+
+    let one = coords_bit(x, y + dir) & !occupied;
+    if one == 0 || y != start_rank {
+        return one;
+    }
+
+    one | coords_bit(x, y + 2 * dir) & !occupied
+}
+
+/// A pawn reaching the last rank must name the piece it becomes,
+/// and no other move may name one
+pub fn validate_promotion(
+    position: &Position,
+    piece: PieceId,
+    destination: Square,
+    promotion: Option<PieceType>,
+) -> Result<(), ChessError> {
+    let is_pawn = position.piece_type(piece) == PieceType::Pawn;
+    let last_rank = if piece.is_white() { 7 } else { 0 };
+    let reaches_last_rank = is_pawn && destination.coords().1 == last_rank;
+
+    match promotion {
+        None if reaches_last_rank => Err(ChessError::PromotionRequired),
+        None => Ok(()),
+        Some(PieceType::Queen | PieceType::Rook | PieceType::Bishop | PieceType::Knight)
+            if reaches_last_rank =>
+        {
+            Ok(())
+        }
+        Some(_) => Err(ChessError::InvalidPromotion),
+    }
+}
+
+/// Moves a piece, removing any opposing piece standing on the destination
+/// (or behind it, for en passant), bringing the rook along when castling,
+/// turning a pawn into the `promotion` piece, updating castling rights,
+/// the en passant square and the move counters, and passing the turn.
+/// Returns the captured piece, if any.
+pub fn apply_move(
+    position: &mut Position,
+    piece: PieceId,
+    destination: Square,
+    promotion: Option<PieceType>,
+) -> Option<PieceId> {
+    let is_white = piece.is_white();
+    let is_pawn = position.piece_type(piece) == PieceType::Pawn;
+    let en_passant = position.en_passant.take();
+    let board_state = &mut position.state;
+    let origin = board_state[piece.index()];
+
+    let victim_square = match en_passant {
+        Some(square) if is_pawn && square == destination => en_passant_victim(square, is_white),
+        _ => destination,
+    };
+
+    let captured = PieceId::all(!is_white).find(|idx| board_state[idx.index()] == victim_square.0);
+    if let Some(victim) = captured {
+        board_state[victim.index()] = CAPTURED;
+    }
+
+    if let Some(castling) = find_castling(piece, origin, destination) {
+        board_state[castling.rook_idx] = castling.rook_to;
+    }
+
+    board_state[piece.index()] = destination.0;
+
+    // Moving the king, or moving from / capturing on a rook's home square, loses the right
+    for castling in CASTLINGS.iter() {
+        if castling.king_idx == piece.index()
+            || castling.rook_from == origin
+            || castling.rook_from == destination.0
+        {
+            position.castling_rights &= !castling.right;
+        }
+    }
+
+    // A double pawn step can be taken en passant on the next ply only
+    if is_pawn && origin.abs_diff(destination.0) == 16 {
+        position.en_passant = Some(Square((origin + destination.0) / 2));
+    }
+
+    if is_pawn && promotion.is_some() {
+        position.promotions[piece.index() - 8] = promotion;
+    }
+
+    // Pawn moves and captures restart the count towards the fifty-move rule
+    if is_pawn || captured.is_some() {
+        position.halfmove_clock = 0;
+    } else {
+        position.halfmove_clock = position.halfmove_clock.saturating_add(1);
+    }
+    if !is_white {
+        position.fullmove_number = position.fullmove_number.saturating_add(1);
+    }
+
+    position.is_white_turn = !is_white;
+
+    captured
+}
+
+fn piece_attacks(piece: PieceType, pos: u8, is_white: bool, occupied: u64) -> u64 {
+    let origin = to_coords(pos);
+
+    match piece {
+        PieceType::Pawn => {
+            let dir = if is_white { 1 } else { -1 };
+            step_attacks(origin, &[(-1, dir), (1, dir)])
+        }
+        PieceType::Knight => step_attacks(origin, &KNIGHT_JUMPS),
+        PieceType::King => step_attacks(origin, &KING_STEPS),
+        PieceType::Rook => ray_attacks(origin, &ORTHOGONAL, occupied),
+        PieceType::Bishop => ray_attacks(origin, &DIAGONAL, occupied),
+        PieceType::Queen => {
+            ray_attacks(origin, &ORTHOGONAL, occupied) | ray_attacks(origin, &DIAGONAL, occupied)
+        }
+    }
+}
+
+/// Attacks of pieces that jump to fixed offsets (pawn captures, knight, king)
+fn step_attacks((x, y): (i8, i8), offsets: &[(i8, i8)]) -> u64 {
+    offsets
+        .iter()
+        .fold(0, |map, &(ox, oy)| map | coords_bit(x + ox, y + oy))
+}
+
+/// Attacks of sliding pieces: each ray stops at the first occupied square
+fn ray_attacks((x, y): (i8, i8), directions: &[(i8, i8)], occupied: u64) -> u64 {
+    let mut map = 0;
+
+    for &(step_x, step_y) in directions {
+        let (mut nx, mut ny) = (x + step_x, y + step_y);
+        while on_board(nx, ny) {
+            let bit = coords_bit(nx, ny);
+            map |= bit;
+            if occupied & bit != 0 {
+                break;
+            }
+            nx += step_x;
+            ny += step_y;
+        }
+    }
+
+    map
+}
+
+fn on_board(x: i8, y: i8) -> bool {
+    (0..8).contains(&x) && (0..8).contains(&y)
+}
+
+/// Slots of one side's pieces
+fn side_slots(is_white: bool) -> Range<usize> {
+    if is_white {
+        0..16
+    } else {
+        16..32
+    }
+}
+
+/// Bitmap of the squares held by the pieces in the given slots
+fn occupancy(board_state: &[u8; 32], slots: Range<usize>) -> u64 {
+    slots
+        .filter(|&idx| board_state[idx] != CAPTURED)
+        .fold(0, |map, idx| map | square_bit(board_state[idx]))
+}
+
+/// Bit of a square in the bitmaps above, 0 when off the board
+fn square_bit(square: u8) -> u64 {
+    let (x, y) = to_coords(square);
+    coords_bit(x, y)
+}
+
+fn coords_bit(x: i8, y: i8) -> u64 {
+    if on_board(x, y) {
+        1 << (y * 8 + x)
+    } else {
+        0
+    }
+}
+
+/// Squares whose bit is set in the bitmap, in ascending bit order
+fn squares(mut map: u64) -> impl Iterator<Item = Square> {
+    std::iter::from_fn(move || {
+        if map == 0 {
+            return None;
+        }
+        let bit = map.trailing_zeros() as i8;
+        map &= map - 1;
+        Some(Square(to_square(bit % 8, bit / 8)))
+    })
 }
 
 fn is_pawn_move(current: u8, destination: u8, is_white: bool, board_state: &[u8; 32]) -> bool {
@@ -93,8 +892,7 @@ fn is_pawn_move(current: u8, destination: u8, is_white: bool, board_state: &[u8;
     if cx == dx && dy - cy == 2 * dir {
         let start_rank = if is_white { 1 } else { 6 };
         if cy == start_rank {
-            let between_square = (cy + dir) * 8 + cx; // compute square in front
-            let between_square = between_square as u8;
+            let between_square = to_square(cx, cy + dir); // compute square in front
             if !board_state.contains(&between_square) && !board_state.contains(&destination) {
                 return true;
             }
@@ -131,7 +929,7 @@ fn is_rook_move(current: u8, destination: u8, is_white: bool, board_state: &[u8;
     while (x, y) != (dx, dy) {
         x += step_x;
         y += step_y;
-        let square = (y * 8 + x) as u8;
+        let square = to_square(x, y);
 
         if (x, y) == (dx, dy) {
             // Destination square: check if occupied by friendly
@@ -192,7 +990,7 @@ fn is_bishop_move(current: u8, destination: u8, is_white: bool, board_state: &[u
     while (x, y) != (dx, dy) {
         x += step_x;
         y += step_y;
-        let square = (y * 8 + x) as u8;
+        let square = to_square(x, y);
 
         if (x, y) == (dx, dy) {
             // Destination: cannot land on friendly
@@ -235,54 +1033,83 @@ fn is_king_move(current: u8, destination: u8, is_white: bool, board_state: &[u8;
     true
 }
 
+/// Squares are 1-based like in `Board::new_chessboard`: a1 = 1, h1 = 8, a8 = 57, h8 = 64.
+/// `CAPTURED` (0) maps off the board.
 fn to_coords(pos: u8) -> (i8, i8) {
-    (pos as i8 % 8, pos as i8 / 8) // (x, y)
+    let idx = pos as i8 - 1;
+    (idx.rem_euclid(8), idx.div_euclid(8)) // (x, y)
+}
+
+fn to_square(x: i8, y: i8) -> u8 {
+    (y * 8 + x + 1) as u8
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn position(state: [u8; 32]) -> Position {
+        Position {
+            state,
+            ..Default::default()
+        }
+    }
+
+    fn to_move(state: [u8; 32], is_white_turn: bool) -> Position {
+        Position {
+            is_white_turn,
+            ..position(state)
+        }
+    }
+
+    fn id(idx: u8) -> PieceId {
+        PieceId::try_from(idx).unwrap()
+    }
+
+    fn sq(square: u8) -> Square {
+        Square::try_from(square).unwrap()
+    }
+
     #[test]
     fn test_pawn_captures() {
         let mut board: [u8; 32] = [0; 32];
-        // Setup white pawn at a2 (pos 8) and black pawn at b3 (pos 17)
-        board[8] = 8; // white pawn index 8
-        board[16] = 17; // black pawn index 16
+        // Setup white pawn at a2 (pos 9) and black pawn at b3 (pos 18)
+        board[8] = 9; // white pawn index 8
+        board[16] = 18; // black pawn index 16
 
-        // Can't move diagonally to empty square
-        assert!(!is_pawn_move(8, 9, true, &board));
+        // Can't move sideways
+        assert!(!is_pawn_move(9, 10, true, &board));
 
         // Can capture black pawn diagonally
-        assert!(is_pawn_move(8, 17, true, &board));
+        assert!(is_pawn_move(9, 18, true, &board));
 
         // Forward moves still work if not blocked
-        assert!(is_pawn_move(8, 16, true, &board));
+        assert!(is_pawn_move(9, 17, true, &board));
     }
 
     #[test]
     fn test_pawn_double_step_blocked() {
         let mut board: [u8; 32] = [0; 32];
-        // White pawn at a2 (pos 8)
-        board[8] = 8;
+        // White pawn at a2 (pos 9)
+        board[8] = 9;
         // Block a3
-        board[16] = 16;
+        board[16] = 17;
 
         // Double move blocked
-        assert!(!is_pawn_move(8, 24, true, &board));
+        assert!(!is_pawn_move(9, 25, true, &board));
 
         // Single move blocked
-        assert!(!is_pawn_move(8, 16, true, &board));
+        assert!(!is_pawn_move(9, 17, true, &board));
     }
 
     #[test]
     fn test_pawn_moves_black_with_capture() {
         let mut board: [u8; 32] = [0; 32];
 
-        // Black pawn at a7 (square 48)
-        board[16] = 48; // first black pawn
-                        // White pawn at b6 (square 41)
-        board[0] = 41; // first white pawn
+        // Black pawn at a7 (square 49)
+        board[16] = 49; // first black pawn
+                        // White pawn at b6 (square 42)
+        board[8] = 42; // first white pawn
 
         // Board diagram for capture test:
         // 8 | . . . .
@@ -291,17 +1118,17 @@ mod tests {
         //     a b c d
 
         // Black pawn a7 captures white pawn b6 (diagonal right)
-        assert!(is_pawn_move(48, 41, false, &board));
+        assert!(is_pawn_move(49, 42, false, &board));
 
         // Board diagram for blocked forward:
         // 8 | . . .
         // 7 | b . .
         // 6 | b w .
         //     a b c
-        board[15] = 40; // second black pawn
+        board[17] = 41; // second black pawn
 
         // Black pawn a7 tries to move forward to a6 (blocked by friendly pawn)
-        assert!(!is_pawn_move(48, 40, false, &board));
+        assert!(!is_pawn_move(49, 41, false, &board));
     }
 
     // pawns start at index 8 but from position 9 to 16
@@ -310,16 +1137,14 @@ mod tests {
         // Setup board: 32 pieces, 0 = empty, positions = current square of each piece
         let mut board: [u8; 32] = [0; 32];
 
-        // Place white pawns at a2..h2 (squares 8..15)
+        // Place white back rank and pawns at a2..h2 (squares 9..16)
         for (i, pos) in (1..=16).enumerate() {
             board[i] = pos;
         }
 
-        // Place a black pawn at b3 (square 17) to test diagonal capture
+        // Place a black pawn at b3 (square 18) to test diagonal capture
         board[16] = 18; // index 16 = black pawn at b3
 
-        println!("{:?}", board);
-
         // Test single and double steps
         assert!(is_pawn_move(9, 17, true, &board)); // a2 -> a3
         assert!(is_pawn_move(9, 25, true, &board)); // a2 -> a4
@@ -329,47 +1154,53 @@ mod tests {
 
         // Test captures
         // 4 | . . . .
-        // 3 | b . . .
-        // 2 | . w . .
+        // 3 | . b . .
+        // 2 | w w w .
         //  ... other pieces ..
         //     a b c d
         // also seen as
-        // 4 |  . . . .
-        // 3 | 17 . . .
-        // 2 |  . 9 . .
+        // 4 |  .  .  . .
+        // 3 |  . 18  . .
+        // 2 |  9 10 11 .
         //  ... other pieces ..
         //     a b c d
-        assert!(is_pawn_move(9, 17, true, &board)); // b2 captures a3 (diagonal left)
+        assert!(is_pawn_move(9, 18, true, &board)); // a2 captures b3 (diagonal right)
+        assert!(is_pawn_move(11, 18, true, &board)); // c2 captures b3 (diagonal left)
 
         // Cannot capture empty square diagonally
-        assert!(!is_pawn_move(8, 15, true, &board)); // a2 -> h3 (no piece)
+        assert!(!is_pawn_move(10, 19, true, &board)); // b2 -> c3 (no piece)
+
+        // No wrapping around the board edge
+        assert!(!is_pawn_move(9, 16, true, &board)); // a2 -> h2
     }
 
     #[test]
     fn test_pawn_moves_black() {
         let mut board: [u8; 32] = [0; 32];
 
-        // Place black pawns at a7..h7 (squares 48..55)
-        for (i, pos) in (48..=55).enumerate() {
+        // Place black pawns at a7..h7 (squares 49..56)
+        for (i, pos) in (49..=56).enumerate() {
             board[16 + i] = pos;
         }
 
-        // Place white pawn at b6 (square 41) to test capture
-        board[1] = 41; // index 1 = white pawn at b6
+        // Place white pawn at b6 (square 42) to test capture
+        board[9] = 42; // index 9 = white pawn at b6
 
         // Test forward moves
-        assert!(is_pawn_move(48, 40, false, &board)); // a7 -> a6
-        assert!(is_pawn_move(48, 32, false, &board)); // a7 -> a5
-        assert!(!is_pawn_move(48, 24, false, &board)); // a7 -> a4 (too far)
+        assert!(is_pawn_move(49, 41, false, &board)); // a7 -> a6
+        assert!(is_pawn_move(49, 33, false, &board)); // a7 -> a5
+        assert!(!is_pawn_move(49, 25, false, &board)); // a7 -> a4 (too far)
 
         // Test capture
-        assert!(is_pawn_move(48, 41, false, &board)); // a7 captures b6
+        assert!(is_pawn_move(49, 42, false, &board)); // a7 captures b6
 
         // Cannot capture off-board
-        assert!(!is_pawn_move(48, 47, false, &board)); // a7 -> h6 (empty)
-                                                       // TOCHECK
-                                                       // assert!(is_pawn_move(49, 40, false, &board));  // b7 captures a6
-        assert!(!is_pawn_move(49, 42, false, &board)); // b7 -> c6 (empty)
+        assert!(!is_pawn_move(49, 48, false, &board)); // a7 -> h6 (empty)
+
+        // Place white pawn at a6 (square 41)
+        board[8] = 41;
+        assert!(is_pawn_move(50, 41, false, &board)); // b7 captures a6
+        assert!(!is_pawn_move(50, 43, false, &board)); // b7 -> c6 (empty)
     }
 
     #[test]
@@ -382,24 +1213,24 @@ mod tests {
         // Valid rook moves
         assert!(is_rook_move(1, 9, true, &board)); // a1 -> a2 (vertical)
         assert!(is_rook_move(1, 57, true, &board)); // a1 -> a8 (vertical)
-        assert!(is_rook_move(1, 7, true, &board)); // a1 -> h1 (horizontal)
+        assert!(is_rook_move(1, 8, true, &board)); // a1 -> h1 (horizontal)
 
         // Invalid diagonal
         assert!(!is_rook_move(1, 10, true, &board)); // a1 -> b2
 
         // Blocked by friendly pawn at a2
-        board[8] = 8; // white pawn (piece idx 8) at square 8 (a2)
-        assert!(!is_rook_move(1, 16, true, &board)); // a1 -> a3 (blocked)
+        board[8] = 9; // white pawn (piece idx 8) at square 9 (a2)
+        assert!(!is_rook_move(1, 17, true, &board)); // a1 -> a3 (blocked)
         board[8] = 0; // clear
 
         // Capture enemy pawn at h1
-        board[16] = 7; // black pawn (piece idx 16) at square 7 (h1)
-        assert!(is_rook_move(1, 7, true, &board)); // capture allowed
+        board[16] = 8; // black pawn (piece idx 16) at square 8 (h1)
+        assert!(is_rook_move(1, 8, true, &board)); // capture allowed
         board[16] = 0; // clear
 
         // Cannot land on friendly piece at h1
-        board[9] = 7; // white pawn (piece idx 9) at square 7 (h1)
-        assert!(!is_rook_move(1, 7, true, &board));
+        board[9] = 8; // white pawn (piece idx 9) at square 8 (h1)
+        assert!(!is_rook_move(1, 8, true, &board));
     }
 
     #[test]
@@ -407,7 +1238,7 @@ mod tests {
         // Setup empty board: 0 = empty, other positions = piece locations
         let mut board: [u8; 32] = [0; 32];
 
-        // Place white knight at b1 (square 1)
+        // Place white knight at b1 (square 2)
         board[1] = 2;
 
         let is_white = true;
@@ -429,9 +1260,9 @@ mod tests {
         assert!(!is_knight_move(2, 18, is_white, &board));
         // --- Move two up, one right: valid ---
         //
-        // 3 | . b . . . . . .
+        // 3 | . . b . . . . .
         // 2 | . . . . . . . .
-        // 1 | a . . . . . . .
+        // 1 | . a . . . . . .
         //     a b c d e f g h
         assert!(is_knight_move(2, 19, is_white, &board));
 
@@ -442,13 +1273,13 @@ mod tests {
         assert!(!is_knight_move(2, 3, is_white, &board));
 
         // --- Invalid: landing on friendly piece ---
-        board[5] = 18; // pretend white piece at square 19 (destination)
-        assert!(!is_knight_move(3, 19, is_white, &board));
+        board[5] = 19; // pretend white piece at c3 (square 19, destination)
+        assert!(!is_knight_move(2, 19, is_white, &board));
 
-        board[10] = 3; // place another friendly piece at square 10
-        assert!(!is_knight_move(2, 10, is_white, &board)); // assert 2
+        board[10] = 12; // place another friendly piece at d2 (square 12)
+        assert!(!is_knight_move(2, 12, is_white, &board)); // assert 2
 
-        board[18] = 17; // pretend black piece at square 10 (destination)
+        board[18] = 17; // pretend black piece at a3 (square 17, destination)
         assert!(is_knight_move(2, 17, is_white, &board));
     }
 
@@ -461,20 +1292,20 @@ mod tests {
         // Place a white bishop at c1 (square 3)
         board[2] = 3; // index 2 = bishop piece ID, value = position 3
 
-        // Place a friendly piece at e3 (square 17) blocking diagonal
+        // Place a friendly piece at a3 (square 17) blocking diagonal
         board[3] = 17;
 
-        // Place an enemy piece at a3 (square 11) for capture
+        // Place an enemy piece at d2 (square 12) for capture
         board[16] = 12;
 
         // Bishop moves diagonally to an empty square
         assert!(is_bishop_move(3, 10, is_white, &board)); // c1 -> b2
 
-        // Bishop blocked by friendly at e3
-        assert!(!is_bishop_move(3, 17, is_white, &board)); // c1 -> e3 blocked
+        // Bishop blocked by friendly at a3
+        assert!(!is_bishop_move(3, 17, is_white, &board)); // c1 -> a3 blocked
 
-        // Bishop captures enemy at a3
-        assert!(is_bishop_move(3, 12, is_white, &board)); // c1 -> a3 capture
+        // Bishop captures enemy at d2
+        assert!(is_bishop_move(3, 12, is_white, &board)); // c1 -> d2 capture
 
         // Bishop cannot move horizontally or vertically
         assert!(!is_bishop_move(3, 4, is_white, &board)); // c1 -> d1 invalid
@@ -493,7 +1324,7 @@ mod tests {
         board[3] = 4;
 
         // --- Horizontal capture ---
-        // 1 | . . . Q . . B X
+        // 1 | . . . Q . B X .
         //     a b c d e f g h
         board[16] = 6; // black piece at f1
         assert!(is_queen_move(4, 6, is_white, &board)); // can capture black
@@ -575,11 +1406,592 @@ mod tests {
         assert!(is_king_move(5, 13, is_white, &board)); // can capture
     }
 
+    #[test]
+    fn test_is_in_check() {
+        let mut board: [u8; 32] = [0; 32];
+
+        // 8 | . . . . r . . .
+        // ...
+        // 2 | . . . . . . . .
+        // 1 | . . . . K . . .
+        //     a b c d e f g h
+        board[4] = 5; // white king at e1
+        board[24] = 61; // black rook at e8
+        assert!(is_in_check(true, &position(board)));
+        assert!(!is_in_check(false, &position(board))); // black king is captured
+
+        // A white pawn on e2 shields the king
+        board[12] = 13;
+        assert!(!is_in_check(true, &position(board)));
+    }
+
+    #[test]
+    fn test_pawn_attacks_forward_only() {
+        let mut board: [u8; 32] = [0; 32];
+
+        // 3 | . . . p . . .
+        // 2 | . . . . K . .
+        //     a b c d e f g
+        board[16] = 20; // black pawn at d3
+        board[4] = 13; // white king at e2
+        assert!(is_in_check(true, &position(board)));
+
+        // A black pawn does not attack backwards
+        board[4] = 29; // white king at e4
+        assert!(!is_in_check(true, &position(board)));
+        assert!(!is_square_attacked(sq(27), false, &position(board))); // c4
+        assert!(is_square_attacked(sq(11), false, &position(board))); // c2
+    }
+
+    #[test]
+    fn test_leaves_king_in_check() {
+        let mut board: [u8; 32] = [0; 32];
+
+        // 8 | . r . . r . . .
+        // ...
+        // 2 | . . . . B . . .
+        // 1 | . . . . K . . .
+        //     a b c d e f g h
+        board[4] = 5; // white king at e1
+        board[5] = 13; // white bishop at e2
+        board[24] = 61; // black rook at e8
+
+        // Pinned bishop cannot leave the e-file
+        assert!(leaves_king_in_check(&position(board), id(5), sq(20))); // e2 -> d3
+
+        // King may step off the file
+        assert!(!leaves_king_in_check(&position(board), id(4), sq(4))); // e1 -> d1
+
+        // Bishop gone: king in check, only moves resolving it are allowed
+        board[5] = 0;
+        board[7] = 58; // white rook at b8
+        assert!(leaves_king_in_check(&position(board), id(4), sq(13))); // e1 -> e2, still on the file
+        assert!(!leaves_king_in_check(&position(board), id(7), sq(61))); // b8 takes e8
+    }
+
+    #[test]
+    fn test_knight_attack_map() {
+        let mut board: [u8; 32] = [0; 32];
+
+        board[25] = 36; // black knight at d5
+        let map = attack_map(false, &position(board));
+
+        // 8 jumps from the middle of the board
+        assert_eq!(map.count_ones(), 8);
+        assert!(is_square_attacked(sq(19), false, &position(board))); // c3
+        assert!(!is_square_attacked(sq(28), false, &position(board))); // d4
+    }
+
+    #[test]
+    fn test_checkmate() {
+        let mut board: [u8; 32] = [0; 32];
+
+        // 8 | . . . . k . . .
+        // 7 | . . . . Q . . .
+        // 6 | . . . . K . . .
+        //     a b c d e f g h
+        board[28] = 61; // black king at e8
+        board[3] = 53; // white queen at e7
+        board[4] = 45; // white king at e6
+
+        assert!(is_in_check(false, &position(board)));
+        assert!(!has_legal_move(&to_move(board, false)));
+
+        // A black rook on b7 can take the unprotected queen
+        board[4] = 29; // white king away to e4
+        board[24] = 50; // black rook at b7
+        assert!(has_legal_move(&to_move(board, false)));
+    }
+
+    #[test]
+    fn test_stalemate() {
+        let mut board: [u8; 32] = [0; 32];
+
+        // 8 | . . . . k . . .
+        // 7 | . . . . P . . .
+        // 6 | . . . . K . . .
+        //     a b c d e f g h
+        board[28] = 61; // black king at e8
+        board[8] = 53; // white pawn at e7
+        board[4] = 45; // white king at e6
+
+        assert!(!is_in_check(false, &position(board)));
+        assert!(!has_legal_move(&to_move(board, false)));
+
+        // A black pawn on c5 still has a push available
+        board[16] = 35;
+        assert!(has_legal_move(&to_move(board, false)));
+    }
+
+    fn castling_position() -> Position {
+        let mut state: [u8; 32] = [0; 32];
+
+        // 8 | . . . . k . . .
+        // ...
+        // 1 | R . . . K . . R
+        //     a b c d e f g h
+        state[0] = 1; // white rook at a1
+        state[4] = 5; // white king at e1
+        state[7] = 8; // white rook at h1
+        state[28] = 61; // black king at e8
+
+        Position {
+            state,
+            castling_rights: ALL_CASTLING_RIGHTS,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_castling_moves_rook_and_drops_rights() {
+        let mut position = castling_position();
+
+        assert!(is_move_legal(&position, id(4), sq(7)).unwrap()); // O-O
+        assert!(is_move_legal(&position, id(4), sq(3)).unwrap()); // O-O-O
+
+        apply_move(&mut position, id(4), sq(7), None);
+        assert_eq!(position.state[4], 7); // king on g1
+        assert_eq!(position.state[7], 6); // rook on f1
+        assert_eq!(position.castling_rights, BLACK_KINGSIDE | BLACK_QUEENSIDE);
+
+        let mut position = castling_position();
+        apply_move(&mut position, id(0), sq(4), None); // a1 -> d1
+        assert_eq!(
+            position.castling_rights,
+            WHITE_KINGSIDE | BLACK_KINGSIDE | BLACK_QUEENSIDE
+        );
+        assert!(!is_move_legal(&position, id(4), sq(3)).unwrap());
+    }
+
+    #[test]
+    fn test_castling_requires_empty_and_safe_path() {
+        let mut position = castling_position();
+
+        // Knight on g1 blocks the kingside
+        position.state[6] = 7;
+        assert!(!is_move_legal(&position, id(4), sq(7)).unwrap());
+        assert!(is_move_legal(&position, id(4), sq(3)).unwrap());
+        position.state[6] = 0;
+
+        // Black rook on f8 covers f1, the king cannot cross it
+        position.state[31] = 62;
+        assert!(!is_move_legal(&position, id(4), sq(7)).unwrap());
+        assert!(is_move_legal(&position, id(4), sq(3)).unwrap());
+
+        // Black rook on e7 gives check, no castling at all
+        position.state[31] = 53;
+        assert!(!is_move_legal(&position, id(4), sq(7)).unwrap());
+        assert!(!is_move_legal(&position, id(4), sq(3)).unwrap());
+    }
+
+    #[test]
+    fn test_castling_right_lost_when_rook_captured() {
+        let mut position = castling_position();
+
+        // Black rook on h5 takes the h1 rook
+        position.state[31] = 40;
+        apply_move(&mut position, id(31), sq(8), None);
+        assert_eq!(position.castling_rights & WHITE_KINGSIDE, 0);
+        assert_ne!(position.castling_rights & WHITE_QUEENSIDE, 0);
+    }
+
+    #[test]
+    fn test_en_passant() {
+        let mut board: [u8; 32] = [0; 32];
+
+        // 7 | . . . p . . .
+        // 6 | . . . . . . .
+        // 5 | . . . . P . .
+        //     a b c d e f g
+        board[4] = 5; // white king at e1
+        board[28] = 61; // black king at e8
+        board[12] = 37; // white pawn at e5
+        board[19] = 52; // black pawn at d7
+        let mut position = position(board);
+
+        // Not available before the double step
+        assert!(!is_move_legal(&position, id(12), sq(44)).unwrap());
+
+        apply_move(&mut position, id(19), sq(36), None); // d7 -> d5
+        assert_eq!(position.en_passant, Some(sq(44))); // d6
+
+        assert!(is_move_legal(&position, id(12), sq(44)).unwrap()); // exd6 e.p.
+        let mut taken = position;
+        assert_eq!(apply_move(&mut taken, id(12), sq(44), None), Some(id(19)));
+        assert_eq!(taken.state[12], 44);
+        assert_eq!(taken.state[19], CAPTURED);
+        assert_eq!(taken.en_passant, None);
+
+        // Expires after any other move
+        apply_move(&mut position, id(4), sq(4), None); // Ke1 - d1
+        assert_eq!(position.en_passant, None);
+        assert!(!is_move_legal(&position, id(12), sq(44)).unwrap());
+    }
+
+    #[test]
+    fn test_en_passant_discovered_check() {
+        let mut board: [u8; 32] = [0; 32];
+
+        // 5 | . K . p P . r .
+        //     a b c d e f g h
+        board[4] = 34; // white king at b5
+        board[12] = 37; // white pawn at e5
+        board[19] = 36; // black pawn at d5, just double stepped
+        board[24] = 39; // black rook at g5
+        board[28] = 61; // black king at e8
+        let mut position = position(board);
+        position.en_passant = Some(sq(44));
+
+        // Both pawns leave the fifth rank, exposing the king
+        assert!(is_move_legal(&position, id(12), sq(44)).unwrap());
+        assert!(leaves_king_in_check(&position, id(12), sq(44)));
+    }
+
+    #[test]
+    fn test_promotion() {
+        let mut board: [u8; 32] = [0; 32];
+
+        // 8 | . . . . . . . .
+        // 7 | . . P . . . . .
+        // 6 | . . . . . . . .
+        // 5 | . . . . . k . .
+        //     a b c d e f g h
+        board[4] = 5; // white king at e1
+        board[28] = 38; // black king at f5
+        board[10] = 51; // white pawn at c7
+        let mut position = position(board);
+
+        // Reaching the last rank requires a piece, other moves can't name one
+        assert!(validate_promotion(&position, id(10), sq(59), None).is_err());
+        assert!(validate_promotion(&position, id(10), sq(59), Some(PieceType::King)).is_err());
+        assert!(validate_promotion(&position, id(10), sq(59), Some(PieceType::Knight)).is_ok());
+        assert!(validate_promotion(&position, id(4), sq(13), Some(PieceType::Queen)).is_err());
+        assert!(validate_promotion(&position, id(4), sq(13), None).is_ok());
+
+        assert!(!is_in_check(false, &position));
+        apply_move(&mut position, id(10), sq(59), Some(PieceType::Queen)); // c8=Q+
+        assert_eq!(position.piece_type(id(10)), PieceType::Queen);
+
+        // The slot now moves and attacks like a queen
+        assert!(is_move_legal(&position, id(10), sq(45)).unwrap()); // c8 - e6
+        assert!(is_move_legal(&position, id(10), sq(63)).unwrap()); // c8 - g8
+        assert!(is_in_check(false, &position));
+    }
+
+    /// The generator must agree with the move-by-move checks `move_piece` runs
+    fn assert_generator_matches_rules(position: &Position) {
+        let generated = generate_legal_moves(position);
+
+        for piece in PieceId::all(position.is_white_turn) {
+            if position.square_of(piece).is_none() {
+                continue;
+            }
+            for destination in (1..=64).map(sq) {
+                let legal = matches!(is_move_legal(position, piece, destination), Ok(true))
+                    && !leaves_king_in_check(position, piece, destination);
+                let found = generated
+                    .iter()
+                    .any(|mv| mv.piece == piece && mv.destination == destination);
+                assert_eq!(legal, found, "{piece:?} to {destination:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_king_moves() {
+        let mut board: [u8; 32] = [0; 32];
+        board[4] = 28; // white king in the middle of the board
+        board[28] = 61; // black king at e8
+
+        let moves = generate_legal_moves(&to_move(board, true));
+        assert_eq!(moves.len(), 8);
+        assert!(moves.iter().all(|mv| mv.piece == id(4)));
+
+        // A black rook on the king's rank takes away the two sideways steps
+        board[24] = 26;
+        let moves = generate_legal_moves(&to_move(board, true));
+        assert_eq!(moves.len(), 6);
+        assert_generator_matches_rules(&to_move(board, true));
+    }
+
+    #[test]
+    fn test_generate_castling_and_pins() {
+        let mut position = castling_position();
+        position.is_white_turn = true;
+
+        let moves = generate_legal_moves(&position);
+        assert!(moves.contains(&Move {
+            piece: id(4),
+            destination: sq(7),
+            promotion: None
+        }));
+        assert!(moves.contains(&Move {
+            piece: id(4),
+            destination: sq(3),
+            promotion: None
+        }));
+        assert_generator_matches_rules(&position);
+
+        // Bishop on e2 pinned by a rook on e7: it can't move at all
+        position.state[5] = 13;
+        position.state[31] = 53;
+        let moves = generate_legal_moves(&position);
+        assert!(moves.iter().all(|mv| mv.piece != id(5)));
+        assert_generator_matches_rules(&position);
+    }
+
+    #[test]
+    fn test_generate_en_passant_and_promotions() {
+        let mut board: [u8; 32] = [0; 32];
+        board[4] = 5; // white king at e1
+        board[28] = 38; // black king at f5
+        board[10] = 51; // white pawn at c7
+        board[12] = 37; // white pawn at e5
+        board[19] = 36; // black pawn at d5, just double stepped
+        board[25] = 58; // black knight at b8
+        let mut position = to_move(board, true);
+        position.en_passant = Some(sq(44));
+
+        let moves = generate_legal_moves(&position);
+        let pawn_moves: Vec<&Move> = moves.iter().filter(|mv| mv.piece == id(10)).collect();
+        // c8 and bxc8, four promotion pieces each
+        assert_eq!(pawn_moves.len(), 8);
+        assert!(pawn_moves.iter().all(|mv| mv.promotion.is_some()));
+        assert!(moves.contains(&Move {
+            piece: id(12),
+            destination: sq(44),
+            promotion: None
+        }));
+        assert_generator_matches_rules(&position);
+    }
+
+    #[test]
+    fn test_has_legal_move_follows_side_to_move() {
+        let mut board: [u8; 32] = [0; 32];
+
+        // Stalemate for black, but white still has moves
+        board[28] = 61; // black king at e8
+        board[8] = 53; // white pawn at e7
+        board[4] = 45; // white king at e6
+
+        assert!(!has_legal_move(&to_move(board, false)));
+        assert!(has_legal_move(&to_move(board, true)));
+        assert!(generate_legal_moves(&to_move(board, false)).is_empty());
+    }
+
+    /// Number of leaf nodes of the legal move tree, the last ply counted in bulk
+    fn perft(position: &Position, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        let moves = generate_legal_moves(position);
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+
+        moves
+            .iter()
+            .map(|mv| {
+                let mut next = *position;
+                apply_move(&mut next, mv.piece, mv.destination, mv.promotion);
+                perft(&next, depth - 1)
+            })
+            .sum()
+    }
+
+    /// Checks perft against reference node counts, starting at depth 1
+    fn assert_perft(fen: &str, expected: &[u64]) {
+        let position = Position::from_fen(fen).unwrap();
+
+        for (depth, &nodes) in (1..).zip(expected) {
+            assert_eq!(perft(&position, depth), nodes, "{fen} at depth {depth}");
+        }
+    }
+
+    // Reference positions and counts from https://www.chessprogramming.org/Perft_Results
+
+    #[test]
+    fn test_perft_start_position() {
+        assert_perft(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &[20, 400, 8_902, 197_281, 4_865_609],
+        );
+    }
+
+    #[test]
+    fn test_perft_kiwipete() {
+        assert_perft(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2_039, 97_862, 4_085_603],
+        );
+    }
+
+    #[test]
+    fn test_perft_position_3() {
+        assert_perft(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[14, 191, 2_812, 43_238, 674_624],
+        );
+    }
+
+    #[test]
+    fn test_perft_position_4() {
+        assert_perft(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9_467, 422_333],
+        );
+    }
+
+    #[test]
+    fn test_perft_position_4_mirrored() {
+        assert_perft(
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+            &[6, 264, 9_467, 422_333],
+        );
+    }
+
+    #[test]
+    fn test_perft_position_5() {
+        assert_perft(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1_486, 62_379, 2_103_487],
+        );
+    }
+
+    #[test]
+    fn test_perft_position_6() {
+        assert_perft(
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            &[46, 2_079, 89_890, 3_894_594],
+        );
+    }
+
+    #[test]
+    fn test_validate_setup() {
+        let setup = |fen| validate_setup(&Position::from_fen(fen).unwrap());
+
+        assert!(setup(fen::START_FEN).is_ok());
+        // K+R vs K drill, black to move
+        assert!(setup("8/8/4k3/8/8/8/8/R3K3 b Q - 0 1").is_ok());
+
+        // A king is missing
+        assert!(setup("8/8/8/8/8/8/8/R3K3 w - - 0 1").is_err());
+        // Pawn on the first rank
+        assert!(setup("4k3/8/8/8/8/8/8/P3K3 w - - 0 1").is_err());
+        // White to move while black is in check
+        assert!(setup("4k3/8/8/8/8/8/8/4RK2 w - - 0 1").is_err());
+        // Black is already stalemated
+        assert!(setup("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1").is_err());
+        // Nobody can win
+        assert!(setup("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1").is_err());
+        // The 75-move rule would end the game on its first move
+        assert!(setup("4k3/8/8/8/8/8/8/R3K3 w - - 149 90").is_ok());
+        assert!(setup("4k3/8/8/8/8/8/8/R3K3 w - - 150 90").is_err());
+
+        // Castling rights without the king or the rook at home
+        assert!(setup("4k3/8/8/8/8/8/8/R2K4 w Q - 0 1").is_err());
+        assert!(setup("4k3/8/8/8/8/8/8/1R2K3 w Q - 0 1").is_err());
+        assert!(setup("r3k3/8/8/8/8/8/8/4K3 w k - 0 1").is_err());
+        assert!(setup("r3k3/8/8/8/8/8/8/4K3 w q - 0 1").is_ok());
+
+        // Black just played d7-d5
+        assert!(setup("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").is_ok());
+        // No black pawn on d5
+        assert!(setup("4k3/8/8/4P3/8/8/8/4K3 w - d6 0 1").is_err());
+        assert!(setup("4k3/8/8/3NP3/8/8/8/4K3 w - d6 0 1").is_err());
+        // The pawn couldn't have passed d6, or come from d7
+        assert!(setup("4k3/8/3n4/3pP3/8/8/8/4K3 w - d6 0 1").is_err());
+        assert!(setup("4k3/3n4/8/3pP3/8/8/8/4K3 w - d6 0 1").is_err());
+        // White just played e2-e4
+        assert!(setup("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1").is_ok());
+        assert!(setup("4k3/8/8/8/3pP3/8/4N3/4K3 b - e3 0 1").is_err());
+    }
+
+    #[test]
+    fn test_insufficient_material() {
+        let dead = |fen| is_insufficient_material(&Position::from_fen(fen).unwrap());
+
+        assert!(dead("4k3/8/8/8/8/8/8/4K3 w - - 0 1"));
+        assert!(dead("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        assert!(dead("4k3/8/8/8/8/8/8/1N2K3 b - - 0 1"));
+        // Bishops on the same colour, c1 and f8 being both dark
+        assert!(dead("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+
+        assert!(!dead(fen::START_FEN));
+        assert!(!dead("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"));
+        assert!(!dead("4k3/8/8/8/8/8/8/R3K3 w - - 0 1"));
+        assert!(!dead("4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1"));
+        assert!(!dead("4k3/8/8/8/8/8/8/1N2KB2 w - - 0 1"));
+        assert!(!dead("4kn2/8/8/8/8/8/8/1N2K3 w - - 0 1"));
+        // Opposite coloured bishops can still mate, with help
+        assert!(!dead("3kb3/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+    }
+
+    #[test]
+    fn test_move_encoding() {
+        let moves = [
+            Move {
+                piece: id(0),
+                destination: sq(1),
+                promotion: None,
+            },
+            Move {
+                piece: id(31),
+                destination: sq(64),
+                promotion: None,
+            },
+            Move {
+                piece: id(10),
+                destination: sq(59),
+                promotion: Some(PieceType::Knight),
+            },
+        ];
+        for mv in moves {
+            assert!(mv.encode() < 1 << 14);
+            assert_eq!(Move::decode(mv.encode()).unwrap(), mv);
+        }
+
+        // Unknown promotion code
+        assert!(Move::decode(0b101).is_err());
+    }
+
+    #[test]
+    fn test_square_and_piece_id_bounds() {
+        assert!(Square::try_from(0).is_err());
+        assert!(Square::try_from(65).is_err());
+        assert_eq!(sq(1).coords(), (0, 0));
+        assert_eq!(Square::from_coords(7, 7), Some(sq(64)));
+        assert_eq!(Square::from_coords(8, 0), None);
+
+        assert!(PieceId::try_from(32).is_err());
+        assert!(id(15).is_white() && !id(16).is_white());
+        assert_eq!(PieceType::from(id(0)), PieceType::Rook);
+        assert_eq!(PieceType::from(id(4)), PieceType::King);
+        assert_eq!(PieceType::from(id(28)), PieceType::King);
+        assert_eq!(PieceType::from(id(27)), PieceType::Queen);
+
+        // A captured piece can't be moved back onto the board
+        let mut board: [u8; 32] = [0; 32];
+        board[4] = 5; // white king at e1
+        board[28] = 61; // black king at e8
+        assert!(is_move_legal(&position(board), id(7), sq(8)).is_err());
+    }
+
     #[test]
     fn test_to_coords() {
-        assert_eq!(to_coords(0), (0, 0));
-        assert_eq!(to_coords(7), (7, 0));
-        assert_eq!(to_coords(8), (0, 1));
-        assert_eq!(to_coords(63), (7, 7));
+        assert_eq!(to_coords(1), (0, 0));
+        assert_eq!(to_coords(8), (7, 0));
+        assert_eq!(to_coords(9), (0, 1));
+        assert_eq!(to_coords(64), (7, 7));
+
+        // Captured pieces are off the board
+        let (x, y) = to_coords(CAPTURED);
+        assert!(!on_board(x, y));
+
+        for square in 1..=64 {
+            let (x, y) = to_coords(square);
+            assert_eq!(to_square(x, y), square);
+        }
     }
 }
@@ -0,0 +1,291 @@
+/// FEN (Forsyth–Edwards Notation) import and export of a `Position`,
+/// so positions can be logged, compared and shared with other chess tools.
+use super::{PieceId, PieceType, Position, Square, CASTLINGS};
+use super::{BLACK_KINGSIDE, BLACK_QUEENSIDE, WHITE_KINGSIDE, WHITE_QUEENSIDE};
+use crate::ChessError;
+use std::str::FromStr;
+
+/// The position every game starts from
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// FEN letter of each castling right, in the order they are written
+const CASTLING_LETTERS: [(char, u8); 4] = [
+    ('K', WHITE_KINGSIDE),
+    ('Q', WHITE_QUEENSIDE),
+    ('k', BLACK_KINGSIDE),
+    ('q', BLACK_QUEENSIDE),
+];
+
+impl Position {
+    /// Parses a FEN string. The move counters may be left out, defaulting to `0 1`.
+    /// Each piece takes a slot it starts the game in, rooks on their home squares
+    /// the ones castling expects; pieces beyond those take a free pawn slot as promoted pieces.
+    pub fn from_fen(fen: &str) -> Result<Self, ChessError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let [placement, side, castling, en_passant, counters @ ..] = fields.as_slice() else {
+            return Err(ChessError::InvalidFen);
+        };
+
+        let mut position = Position {
+            is_white_turn: match *side {
+                "w" => true,
+                "b" => false,
+                _ => return Err(ChessError::InvalidFen),
+            },
+            fullmove_number: 1,
+            ..Default::default()
+        };
+
+        // Home rooks first, so another rook can't take their slot
+        let mut pieces = parse_placement(placement)?;
+        pieces.sort_by_key(|&(_, _, square)| {
+            !CASTLINGS
+                .iter()
+                .any(|castling| castling.rook_from == square.0)
+        });
+
+        let mut leftovers = Vec::new();
+        for (piece, is_white, square) in pieces {
+            let home = CASTLINGS
+                .iter()
+                .find(|castling| castling.rook_from == square.0 && piece == PieceType::Rook)
+                .map(|castling| PieceId(castling.rook_idx as u8));
+            let slot = home
+                .into_iter()
+                .chain(piece.indices())
+                .find(|&slot| slot.is_white() == is_white && position.square_of(slot).is_none());
+
+            match slot {
+                Some(slot) => position.state[slot.index()] = square.0,
+                None => leftovers.push((piece, is_white, square)),
+            }
+        }
+
+        // Only promoted pieces can be spare, and they live in pawn slots
+        for (piece, is_white, square) in leftovers {
+            if matches!(piece, PieceType::Pawn | PieceType::King) {
+                return Err(ChessError::InvalidFen);
+            }
+            let slot = PieceType::Pawn
+                .indices()
+                .into_iter()
+                .find(|&slot| slot.is_white() == is_white && position.square_of(slot).is_none())
+                .ok_or(ChessError::InvalidFen)?;
+            position.state[slot.index()] = square.0;
+            position.promotions[slot.index() - 8] = Some(piece);
+        }
+
+        if *castling != "-" {
+            for letter in castling.chars() {
+                let (_, right) = CASTLING_LETTERS
+                    .iter()
+                    .find(|(right_letter, _)| *right_letter == letter)
+                    .ok_or(ChessError::InvalidFen)?;
+                position.castling_rights |= right;
+            }
+        }
+
+        if *en_passant != "-" {
+            let square = Square::from_str(en_passant).map_err(|_| ChessError::InvalidFen)?;
+            // The square skipped by the opponent's double step
+            let rank = if position.is_white_turn { 5 } else { 2 };
+            if square.coords().1 != rank {
+                return Err(ChessError::InvalidFen);
+            }
+            position.en_passant = Some(square);
+        }
+
+        match counters {
+            [] => {}
+            [halfmove, fullmove] => {
+                position.halfmove_clock = halfmove.parse().map_err(|_| ChessError::InvalidFen)?;
+                position.fullmove_number = fullmove.parse().map_err(|_| ChessError::InvalidFen)?;
+                if position.fullmove_number == 0 {
+                    return Err(ChessError::InvalidFen);
+                }
+            }
+            _ => return Err(ChessError::InvalidFen),
+        }
+
+        Ok(position)
+    }
+
+    /// Writes the position as a FEN string
+    pub fn to_fen(&self) -> String {
+        let mut board = [None; 64];
+        for piece in PieceId::all(true).chain(PieceId::all(false)) {
+            if let Some(square) = self.square_of(piece) {
+                let letter = self.piece_type(piece).letter();
+                board[square.0 as usize - 1] = Some(if piece.is_white() {
+                    letter
+                } else {
+                    letter.to_ascii_lowercase()
+                });
+            }
+        }
+
+        let ranks: Vec<String> = board
+            .chunks(8)
+            .rev()
+            .map(|rank| {
+                let mut text = String::new();
+                let mut empty = 0;
+                for square in rank {
+                    match square {
+                        Some(letter) => {
+                            if empty > 0 {
+                                text.push_str(&empty.to_string());
+                                empty = 0;
+                            }
+                            text.push(*letter);
+                        }
+                        None => empty += 1,
+                    }
+                }
+                if empty > 0 {
+                    text.push_str(&empty.to_string());
+                }
+                text
+            })
+            .collect();
+
+        let castling: String = CASTLING_LETTERS
+            .iter()
+            .filter(|(_, right)| self.castling_rights & right != 0)
+            .map(|(letter, _)| letter)
+            .collect();
+
+        format!(
+            "{} {} {} {} {} {}",
+            ranks.join("/"),
+            if self.is_white_turn { "w" } else { "b" },
+            if castling.is_empty() { "-" } else { &castling },
+            self.en_passant
+                .map_or("-".to_string(), |square| square.to_string()),
+            self.halfmove_clock,
+            self.fullmove_number,
+        )
+    }
+}
+
+/// Pieces of the placement field, with their colour, from a8 to h1
+fn parse_placement(placement: &str) -> Result<Vec<(PieceType, bool, Square)>, ChessError> {
+    let ranks: Vec<&str> = placement.split('/').collect();
+    if ranks.len() != 8 {
+        return Err(ChessError::InvalidFen);
+    }
+
+    let mut pieces = Vec::new();
+    for (row, rank) in ranks.iter().enumerate() {
+        let y = 7 - row as i8;
+        let mut x = 0;
+        for letter in rank.chars() {
+            if let Some(empty @ 1..=8) = letter.to_digit(10) {
+                x += empty as i8;
+                continue;
+            }
+            let piece = PieceType::from_letter(letter).ok_or(ChessError::InvalidFen)?;
+            let square = Square::from_coords(x, y).ok_or(ChessError::InvalidFen)?;
+            pieces.push((piece, letter.is_ascii_uppercase(), square));
+            x += 1;
+        }
+        if x != 8 {
+            return Err(ChessError::InvalidFen);
+        }
+    }
+
+    Ok(pieces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_logic::{apply_move, ALL_CASTLING_RIGHTS};
+
+    #[test]
+    fn test_start_position() {
+        let position = Position::from_fen(START_FEN).unwrap();
+
+        // Every piece lands in the slot `Board::new_chessboard` gives it
+        for (idx, &square) in position.state.iter().enumerate() {
+            let expected = match idx {
+                0..=15 => idx as u8 + 1,
+                _ => idx as u8 + 33,
+            };
+            assert_eq!(square, expected, "slot {idx}");
+        }
+        assert!(position.is_white_turn);
+        assert_eq!(position.castling_rights, ALL_CASTLING_RIGHTS);
+        assert_eq!(position.en_passant, None);
+        assert_eq!(position.to_fen(), START_FEN);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 12 40",
+        ] {
+            assert_eq!(Position::from_fen(fen).unwrap().to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn test_extra_pieces_become_promotions() {
+        // Three white queens and a third knight
+        let position = Position::from_fen("4k3/8/8/8/8/8/QQQ5/NNN1K3 w - - 0 1").unwrap();
+
+        let promoted: Vec<PieceType> = position.promotions.iter().flatten().copied().collect();
+        assert_eq!(
+            promoted,
+            [PieceType::Queen, PieceType::Queen, PieceType::Knight]
+        );
+        assert_eq!(position.to_fen(), "4k3/8/8/8/8/8/QQQ5/NNN1K3 w - - 0 1");
+    }
+
+    #[test]
+    fn test_counters_follow_moves() {
+        let mut position = Position::from_fen(START_FEN).unwrap();
+
+        apply_move(&mut position, PieceId(12), Square(29), None); // e4
+        assert_eq!(
+            position.to_fen(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+        );
+
+        apply_move(&mut position, PieceId(30), Square(46), None); // Nf6
+        apply_move(&mut position, PieceId(6), Square(22), None); // Nf3
+        assert_eq!(
+            position.to_fen(),
+            "rnbqkb1r/pppppppp/5n2/8/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 2 2"
+        );
+    }
+
+    #[test]
+    fn test_counters_are_optional() {
+        let position = Position::from_fen("4k3/8/8/8/8/8/8/4K3 b - -").unwrap();
+        assert_eq!(position.to_fen(), "4k3/8/8/8/8/8/8/4K3 b - - 0 1");
+    }
+
+    #[test]
+    fn test_rejects_malformed_fen() {
+        for fen in [
+            "",
+            "8/8/8/8/8/8/8 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K4 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4X3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 x - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w X - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - e4 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 0",
+            "4k3/8/8/8/8/8/8/4K3 w - - 0",
+            // Nine pawns, or a second king, can't be told apart from promoted pieces
+            "4k3/8/8/8/8/P7/PPPPPPPP/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/3KK3 w - - 0 1",
+        ] {
+            assert!(Position::from_fen(fen).is_err(), "{fen}");
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Move notation: Standard Algebraic Notation (SAN) as used in PGN,
+//! and UCI long algebraic notation as used by engines
+use super::{
+    apply_move, find_castling, generate_legal_moves, has_legal_move, is_in_check, to_coords,
+    Castling, Move, PieceType, Position, Square,
+};
+use crate::error::ChessError;
+
+/// SAN of a legal move in the given position, e.g. `Nbd7`, `exd6`, `e8=Q+` or `O-O#`
+pub fn to_san(position: &Position, mv: &Move) -> String {
+    let origin = position.state[mv.piece.index()];
+    let piece_type = position.piece_type(mv.piece);
+    let mut san = String::new();
+
+    if let Some(castling) = castling_of(position, mv) {
+        san.push_str(if castling.rook_from < castling.king_from {
+            "O-O-O"
+        } else {
+            "O-O"
+        });
+    } else {
+        let (from_x, from_y) = to_coords(origin);
+        let destination = mv.destination.to_string();
+        let is_capture = position.state.contains(&mv.destination.0)
+            || piece_type == PieceType::Pawn && position.en_passant == Some(mv.destination);
+
+        if piece_type == PieceType::Pawn {
+            if is_capture {
+                san.push(file_letter(from_x));
+            }
+        } else {
+            san.push(piece_type.letter());
+
+            // Other pieces of the same kind that could go there too
+            let rivals: Vec<(i8, i8)> = generate_legal_moves(position)
+                .iter()
+                .filter(|other| {
+                    other.destination == mv.destination
+                        && other.piece != mv.piece
+                        && position.piece_type(other.piece) == piece_type
+                })
+                .map(|other| to_coords(position.state[other.piece.index()]))
+                .collect();
+
+            if !rivals.is_empty() {
+                if rivals.iter().all(|&(x, _)| x != from_x) {
+                    san.push(file_letter(from_x));
+                } else if rivals.iter().all(|&(_, y)| y != from_y) {
+                    san.push(rank_digit(from_y));
+                } else {
+                    san.push(file_letter(from_x));
+                    san.push(rank_digit(from_y));
+                }
+            }
+        }
+
+        if is_capture {
+            san.push('x');
+        }
+        san.push_str(&destination);
+
+        if let Some(promotion) = mv.promotion {
+            san.push('=');
+            san.push(promotion.letter());
+        }
+    }
+
+    let mut next = *position;
+    apply_move(&mut next, mv.piece, mv.destination, mv.promotion);
+    if is_in_check(next.is_white_turn, &next) {
+        san.push(if has_legal_move(&next) { '+' } else { '#' });
+    }
+
+    san
+}
+
+/// Legal move written in SAN. Check and annotation suffixes, `e.p.`, castling with zeros
+/// and needless disambiguation (`Ngf3` when only one knight reaches f3) are accepted.
+pub fn from_san(position: &Position, san: &str) -> Result<Move, ChessError> {
+    let suffixes = ['+', '#', '!', '?'];
+    let san = san.trim().trim_end_matches(suffixes);
+    let san = san.strip_suffix("e.p.").unwrap_or(san).trim_end();
+    let san = san.trim_end_matches(suffixes);
+    let legal = generate_legal_moves(position);
+
+    let kingside = match san {
+        "O-O" | "0-0" => Some(true),
+        "O-O-O" | "0-0-0" => Some(false),
+        _ => None,
+    };
+    if let Some(kingside) = kingside {
+        return legal
+            .into_iter()
+            .find(|mv| {
+                castling_of(position, mv)
+                    .is_some_and(|castling| (castling.rook_from > castling.king_from) == kingside)
+            })
+            .ok_or(ChessError::IllegalMove);
+    }
+
+    if !san.is_ascii() {
+        return Err(ChessError::InvalidNotation);
+    }
+    let mut rest = san;
+
+    // Lowercase letters are files, so only uppercase ones name pieces
+    let piece_type = match rest.chars().next().filter(char::is_ascii_uppercase) {
+        Some(letter) => {
+            rest = &rest[1..];
+            PieceType::from_letter(letter).ok_or(ChessError::InvalidNotation)?
+        }
+        None => PieceType::Pawn,
+    };
+
+    // `e8=Q`, or `e8Q`
+    let promotion = match rest.chars().last().filter(char::is_ascii_uppercase) {
+        Some(letter) => {
+            rest = &rest[..rest.len() - 1];
+            rest = rest.strip_suffix('=').unwrap_or(rest);
+            Some(PieceType::from_letter(letter).ok_or(ChessError::InvalidNotation)?)
+        }
+        None => None,
+    };
+
+    let split = rest
+        .len()
+        .checked_sub(2)
+        .ok_or(ChessError::InvalidNotation)?;
+    let (hints, destination) = rest.split_at(split);
+    let destination: Square = destination
+        .parse()
+        .map_err(|_| ChessError::InvalidNotation)?;
+
+    // Whatever is left is the origin file and/or rank
+    let (mut file, mut rank) = (None, None);
+    for hint in hints.strip_suffix('x').unwrap_or(hints).bytes() {
+        match hint {
+            b'a'..=b'h' if file.is_none() && rank.is_none() => file = Some((hint - b'a') as i8),
+            b'1'..=b'8' if rank.is_none() => rank = Some((hint - b'1') as i8),
+            _ => return Err(ChessError::InvalidNotation),
+        }
+    }
+
+    let mut candidates = legal.into_iter().filter(|mv| {
+        let (x, y) = to_coords(position.state[mv.piece.index()]);
+        position.piece_type(mv.piece) == piece_type
+            && mv.destination == destination
+            && mv.promotion == promotion
+            && castling_of(position, mv).is_none()
+            && file.is_none_or(|file| file == x)
+            && rank.is_none_or(|rank| rank == y)
+    });
+
+    match (candidates.next(), candidates.next()) {
+        (Some(mv), None) => Ok(mv),
+        (None, _) => Err(ChessError::IllegalMove),
+        // Several pieces fit, the notation is ambiguous
+        (Some(_), Some(_)) => Err(ChessError::InvalidNotation),
+    }
+}
+
+/// UCI notation of a move: origin, destination and promotion, e.g. `e2e4`, `e1g1` or `e7e8q`
+pub fn to_uci(position: &Position, mv: &Move) -> String {
+    let mut uci = format!(
+        "{}{}",
+        Square(position.state[mv.piece.index()]),
+        mv.destination
+    );
+    if let Some(promotion) = mv.promotion {
+        uci.push(promotion.letter().to_ascii_lowercase());
+    }
+    uci
+}
+
+/// Legal move written in UCI notation
+pub fn from_uci(position: &Position, uci: &str) -> Result<Move, ChessError> {
+    if !uci.is_ascii() || !(4..=5).contains(&uci.len()) {
+        return Err(ChessError::InvalidNotation);
+    }
+    let origin: Square = uci[0..2].parse().map_err(|_| ChessError::InvalidNotation)?;
+    let destination: Square = uci[2..4].parse().map_err(|_| ChessError::InvalidNotation)?;
+    let promotion = match uci[4..].chars().next() {
+        Some(letter) => Some(PieceType::from_letter(letter).ok_or(ChessError::InvalidNotation)?),
+        None => None,
+    };
+
+    generate_legal_moves(position)
+        .into_iter()
+        .find(|mv| {
+            position.state[mv.piece.index()] == origin.0
+                && mv.destination == destination
+                && mv.promotion == promotion
+        })
+        .ok_or(ChessError::IllegalMove)
+}
+
+fn castling_of(position: &Position, mv: &Move) -> Option<&'static Castling> {
+    find_castling(mv.piece, position.state[mv.piece.index()], mv.destination)
+}
+
+fn file_letter(x: i8) -> char {
+    (b'a' + x as u8) as char
+}
+
+fn rank_digit(y: i8) -> char {
+    (b'1' + y as u8) as char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_logic::{PieceId, Square};
+
+    /// SAN of every legal move of the position
+    fn all_san(fen: &str) -> Vec<String> {
+        let position = Position::from_fen(fen).unwrap();
+        generate_legal_moves(&position)
+            .iter()
+            .map(|mv| to_san(&position, mv))
+            .collect()
+    }
+
+    fn san(fen: &str, piece: u8, destination: &str, promotion: Option<PieceType>) -> String {
+        let position = Position::from_fen(fen).unwrap();
+        let mv = Move {
+            piece: PieceId::try_from(piece).unwrap(),
+            destination: destination.parse::<Square>().unwrap(),
+            promotion,
+        };
+        to_san(&position, &mv)
+    }
+
+    #[test]
+    fn test_pieces_and_pawns() {
+        let moves = all_san(crate::game_logic::fen::START_FEN);
+        assert_eq!(moves.len(), 20);
+        for expected in ["e4", "a3", "Nf3", "Na3"] {
+            assert!(moves.iter().any(|san| san == expected), "{expected}");
+        }
+    }
+
+    #[test]
+    fn test_disambiguation() {
+        // Knights on b1 and f1 both reach d2, rooks on a1 and a5 both reach a3
+        let fen = "4k3/8/8/R7/8/8/8/RN2KN2 w - - 0 1";
+        assert_eq!(san(fen, 1, "d2", None), "Nbd2");
+        assert_eq!(san(fen, 0, "a3", None), "R1a3");
+        assert_eq!(san(fen, 7, "h5", None), "Rh5");
+
+        // Three queens reaching d4: by file, by rank, and by both
+        let fen = "8/8/6k1/8/Q6Q/8/8/Q3K3 w - - 0 1";
+        let position = Position::from_fen(fen).unwrap();
+        let d4 = "d4".parse::<Square>().unwrap();
+        let mut to_d4: Vec<String> = generate_legal_moves(&position)
+            .iter()
+            .filter(|mv| mv.destination == d4)
+            .map(|mv| to_san(&position, mv))
+            .collect();
+        to_d4.sort();
+        assert_eq!(to_d4, ["Q1d4", "Qa4d4", "Qhd4"]);
+    }
+
+    #[test]
+    fn test_captures_castling_promotion() {
+        // En passant
+        let fen = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1";
+        assert_eq!(san(fen, 8, "d6", None), "exd6");
+
+        // Castling both ways, the queenside one giving check
+        let fen = "3k4/8/8/8/8/8/8/R3K2R w KQ - 0 1";
+        assert_eq!(san(fen, 4, "g1", None), "O-O");
+        assert_eq!(san(fen, 4, "c1", None), "O-O-O+");
+
+        // Promotion with capture and check
+        let fen = "1n2k3/2P5/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(san(fen, 8, "b8", Some(PieceType::Queen)), "cxb8=Q+");
+    }
+
+    #[test]
+    fn test_mate() {
+        // Scholar's mate
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 0 1";
+        assert_eq!(san(fen, 3, "f7", None), "Qxf7#");
+    }
+
+    #[test]
+    fn test_round_trips() {
+        // Kiwipete, then a position with promotions and en passant
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1",
+        ] {
+            let position = Position::from_fen(fen).unwrap();
+            for mv in generate_legal_moves(&position) {
+                assert_eq!(from_san(&position, &to_san(&position, &mv)).unwrap(), mv);
+                assert_eq!(from_uci(&position, &to_uci(&position, &mv)).unwrap(), mv);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_san() {
+        let start = Position::from_fen(crate::game_logic::fen::START_FEN).unwrap();
+        let nf3 = from_san(&start, "Nf3").unwrap();
+        assert_eq!(to_uci(&start, &nf3), "g1f3");
+        // Needless disambiguation and annotations
+        assert_eq!(from_san(&start, "Ngf3!?").unwrap(), nf3);
+        assert_eq!(from_san(&start, "Ng1f3").unwrap(), nf3);
+        assert!(matches!(
+            from_san(&start, "Nbf3"),
+            Err(ChessError::IllegalMove)
+        ));
+        assert!(matches!(
+            from_san(&start, "e5"),
+            Err(ChessError::IllegalMove)
+        ));
+        for garbage in ["", "Zf3", "Nf9", "N3gf3", "e4x", "Nf"] {
+            assert!(
+                matches!(from_san(&start, garbage), Err(ChessError::InvalidNotation)),
+                "{garbage}"
+            );
+        }
+
+        let position = Position::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+        assert_eq!(
+            to_uci(&position, &from_san(&position, "exd6 e.p.").unwrap()),
+            "e5d6"
+        );
+
+        let position = Position::from_fen("r3k3/8/8/8/8/8/8/4K2R w Kq - 0 1").unwrap();
+        assert_eq!(
+            to_uci(&position, &from_san(&position, "0-0").unwrap()),
+            "e1g1"
+        );
+        assert!(matches!(
+            from_san(&position, "O-O-O"),
+            Err(ChessError::IllegalMove)
+        ));
+        // The king's step isn't written as a king move
+        assert!(matches!(
+            from_san(&position, "Kg1"),
+            Err(ChessError::IllegalMove)
+        ));
+
+        // Both knights reach d2
+        let position = Position::from_fen("4k3/8/8/R7/8/8/8/RN2KN2 w - - 0 1").unwrap();
+        assert!(matches!(
+            from_san(&position, "Nd2"),
+            Err(ChessError::InvalidNotation)
+        ));
+        assert_eq!(
+            to_uci(&position, &from_san(&position, "Nfd2").unwrap()),
+            "f1d2"
+        );
+
+        let position = Position::from_fen("1n2k3/2P5/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let promotion = from_san(&position, "cxb8Q+").unwrap();
+        assert_eq!(promotion.promotion, Some(PieceType::Queen));
+        assert!(matches!(
+            from_san(&position, "cxb8"),
+            Err(ChessError::IllegalMove)
+        ));
+    }
+
+    #[test]
+    fn test_parse_uci() {
+        let position = Position::from_fen("1n2k3/2P5/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let promotion = from_uci(&position, "c7b8n").unwrap();
+        assert_eq!(to_san(&position, &promotion), "cxb8=N");
+        assert!(matches!(
+            from_uci(&position, "c7b8"),
+            Err(ChessError::IllegalMove)
+        ));
+        assert!(matches!(
+            from_uci(&position, "c7b8k"),
+            Err(ChessError::IllegalMove)
+        ));
+        assert!(matches!(
+            from_uci(&position, "e1e3"),
+            Err(ChessError::IllegalMove)
+        ));
+        for garbage in ["e1", "e1e2e3", "i1e2", "e1e2x", "e1é2"] {
+            assert!(
+                matches!(
+                    from_uci(&position, garbage),
+                    Err(ChessError::InvalidNotation)
+                ),
+                "{garbage}"
+            );
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Zobrist hashing of positions, to spot repetitions
+use super::{en_passant_victim, PieceId, PieceType, Position, Square};
+
+// Key numbering: one key per (colour, piece type, square), then the side to move,
+// the four castling rights and the eight en passant files
+const SIDE_KEY: u64 = 2 * 6 * 64;
+const CASTLING_KEYS: u64 = SIDE_KEY + 1;
+const EN_PASSANT_KEYS: u64 = CASTLING_KEYS + 4;
+
+impl Position {
+    /// Hash of what makes positions equal for repetitions: pieces, side to move,
+    /// castling rights, and en passant when a pawn stands ready to take.
+    /// Pieces hash the same whichever slot they sit in.
+    pub fn zobrist_hash(&self) -> u64 {
+        let mut hash = 0;
+
+        for piece in PieceId::all(true).chain(PieceId::all(false)) {
+            if let Some(square) = self.square_of(piece) {
+                let colour = if piece.is_white() { 0 } else { 6 };
+                let kind = colour + piece_type_number(self.piece_type(piece));
+                hash ^= key(kind * 64 + u64::from(square.0 - 1));
+            }
+        }
+
+        if !self.is_white_turn {
+            hash ^= key(SIDE_KEY);
+        }
+
+        for right in 0..4 {
+            if self.castling_rights & (1 << right) != 0 {
+                hash ^= key(CASTLING_KEYS + right);
+            }
+        }
+
+        if let Some(en_passant) = self
+            .en_passant
+            .filter(|&square| self.can_take_en_passant(square))
+        {
+            let (file, _) = en_passant.coords();
+            hash ^= key(EN_PASSANT_KEYS + file as u64);
+        }
+
+        hash
+    }
+
+    /// Whether a pawn of the side to move stands next to the pawn that just
+    /// stepped over `en_passant`. Pins are ignored.
+    fn can_take_en_passant(&self, en_passant: Square) -> bool {
+        let (x, y) = en_passant_victim(en_passant, self.is_white_turn).coords();
+
+        [x - 1, x + 1]
+            .into_iter()
+            .filter_map(|x| Square::from_coords(x, y))
+            .any(|square| {
+                PieceId::all(self.is_white_turn).any(|piece| {
+                    self.state[piece.index()] == square.0
+                        && self.piece_type(piece) == PieceType::Pawn
+                })
+            })
+    }
+}
+
+fn piece_type_number(piece_type: PieceType) -> u64 {
+    match piece_type {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        PieceType::King => 5,
+    }
+}
+
+/// Pseudo-random key number `n` (SplitMix64), computed instead of stored
+/// so the program doesn't carry a table of 781 keys
+fn key(n: u64) -> u64 {
+    let mut z = n.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_logic::fen::START_FEN;
+    use crate::game_logic::{apply_move, generate_legal_moves};
+    use std::collections::HashSet;
+
+    fn hash(fen: &str) -> u64 {
+        Position::from_fen(fen).unwrap().zobrist_hash()
+    }
+
+    #[test]
+    fn test_keys_are_distinct() {
+        let keys: HashSet<u64> = (0..EN_PASSANT_KEYS + 8).map(key).collect();
+        assert_eq!(keys.len() as u64, EN_PASSANT_KEYS + 8);
+    }
+
+    #[test]
+    fn test_same_position_same_hash() {
+        // Knights out and back: the same position, with different counters
+        let mut position = Position::from_fen(START_FEN).unwrap();
+        let start = position.zobrist_hash();
+        for uci in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            let mv = crate::game_logic::notation::from_uci(&position, uci).unwrap();
+            apply_move(&mut position, mv.piece, mv.destination, mv.promotion);
+        }
+        assert_eq!(position.zobrist_hash(), start);
+
+        // Rooks swapped between their slots
+        assert_eq!(
+            hash("4k3/8/8/8/8/8/8/R3K2R w - - 0 1"),
+            hash("4k3/8/8/8/8/8/8/R3K2R w - - 12 40")
+        );
+        let mut swapped = Position::from_fen("4k3/8/8/8/8/8/8/R3K2R w - - 0 1").unwrap();
+        swapped.state.swap(0, 7);
+        assert_eq!(
+            swapped.zobrist_hash(),
+            hash("4k3/8/8/8/8/8/8/R3K2R w - - 0 1")
+        );
+    }
+
+    #[test]
+    fn test_what_tells_positions_apart() {
+        let base = hash(START_FEN);
+        assert_ne!(
+            base,
+            hash("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1")
+        );
+        assert_ne!(
+            base,
+            hash("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w Kkq - 0 1")
+        );
+        // Piece types count, not just the occupied squares
+        assert_ne!(
+            hash("4k3/8/8/8/8/8/P7/4K3 w - - 0 1"),
+            hash("4k3/8/8/8/8/8/Q7/4K3 w - - 0 1")
+        );
+
+        // En passant only matters when a pawn can take
+        assert_ne!(
+            hash("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1"),
+            hash("4k3/8/8/3pP3/8/8/8/4K3 w - - 0 1")
+        );
+        assert_eq!(
+            hash("4k3/8/8/3p4/8/8/8/4K3 w - d6 0 1"),
+            hash("4k3/8/8/3p4/8/8/8/4K3 w - - 0 1")
+        );
+    }
+
+    #[test]
+    fn test_few_collisions() {
+        // All positions two plies from the start are told apart
+        let start = Position::from_fen(START_FEN).unwrap();
+        let mut positions = HashSet::new();
+        let mut hashes = HashSet::new();
+        for first in generate_legal_moves(&start) {
+            let mut after_first = start;
+            apply_move(&mut after_first, first.piece, first.destination, None);
+            for second in generate_legal_moves(&after_first) {
+                let mut after_second = after_first;
+                apply_move(&mut after_second, second.piece, second.destination, None);
+                positions.insert(after_second.to_fen());
+                hashes.insert(after_second.zobrist_hash());
+            }
+        }
+        assert_eq!(positions.len(), 400);
+        assert_eq!(hashes.len(), 400);
+    }
+}
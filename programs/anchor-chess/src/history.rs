@@ -0,0 +1,189 @@
+use crate::game_logic::{apply_move, generate_legal_moves, Move, Position};
+use crate::ChessError;
+use anchor_lang::prelude::*;
+
+/// Longest FEN `Position::to_fen` can write: 71 characters of placement,
+/// side, castling, en passant, two 5 digit counters and the spaces in between
+pub const MAX_FEN_LEN: usize = 93;
+
+/// Companion of a `Board` recording every move played,
+/// at the PDA `["moves", board]`. Grows by one entry on each `move_piece`,
+/// while `positions` shrinks back after captures and pawn moves.
+/// The account itself never shrinks, its rent goes back to the maker on close.
+#[derive(InitSpace)]
+#[account(discriminator = 2)]
+pub struct MoveHistory {
+    pub board: Pubkey,
+    pub bump: u8,
+    /// Position the game started from
+    #[max_len(MAX_FEN_LEN)]
+    pub start_fen: String,
+    /// Moves in the order they were played, see `Move::encode`
+    #[max_len(0)]
+    pub moves: Vec<u16>,
+    /// Hashes of the positions since the last capture or pawn move, the current one last,
+    /// see `Position::zobrist_hash`. Earlier positions can't come back.
+    #[max_len(0)]
+    pub positions: Vec<u64>,
+}
+
+impl MoveHistory {
+    /// Account size once `moves` moves and `positions` position hashes are recorded
+    pub fn space(moves: usize, positions: usize) -> usize {
+        Self::DISCRIMINATOR.len()
+            + Self::INIT_SPACE
+            + moves * std::mem::size_of::<u16>()
+            + positions * std::mem::size_of::<u64>()
+    }
+
+    /// Account size before recording one more move, never less than the
+    /// current `data_len`: room freed by cleared positions is reused
+    /// rather than refunded to whoever happens to move
+    pub fn space_for_next_move(&self, data_len: usize) -> usize {
+        Self::space(self.moves.len() + 1, self.positions.len() + 1).max(data_len)
+    }
+
+    /// Records a move and the position it led to
+    pub fn record(&mut self, mv: Move, position: &Position) {
+        self.moves.push(mv.encode());
+        if position.halfmove_clock == 0 {
+            self.positions.clear();
+        }
+        self.positions.push(position.zobrist_hash());
+    }
+
+    /// How many times the current position has occurred, counting this time
+    pub fn repetitions(&self) -> usize {
+        let Some(current) = self.positions.last() else {
+            return 0;
+        };
+        // Only every other position has the same side to move
+        self.positions
+            .iter()
+            .rev()
+            .step_by(2)
+            .filter(|&hash| hash == current)
+            .count()
+    }
+
+    /// Plays the recorded moves from the starting position,
+    /// failing on the first one that isn't legal
+    pub fn replay(&self) -> Result<Position> {
+        let mut position = Position::from_fen(&self.start_fen)?;
+
+        for &encoded in &self.moves {
+            let mv = Move::decode(encoded)?;
+            require!(
+                generate_legal_moves(&position).contains(&mv),
+                ChessError::IllegalMove
+            );
+            apply_move(&mut position, mv.piece, mv.destination, mv.promotion);
+        }
+
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_logic::fen::START_FEN;
+    use crate::game_logic::{PieceId, Square};
+
+    fn play(piece: u8, destination: u8) -> Move {
+        Move {
+            piece: PieceId::try_from(piece).unwrap(),
+            destination: Square::try_from(destination).unwrap(),
+            promotion: None,
+        }
+    }
+
+    fn history(moves: &[(u8, u8)]) -> MoveHistory {
+        MoveHistory {
+            board: Pubkey::new_unique(),
+            bump: 255,
+            start_fen: START_FEN.to_string(),
+            moves: moves
+                .iter()
+                .map(|&(piece, destination)| play(piece, destination).encode())
+                .collect(),
+            positions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_replay() {
+        // 1. e4 e5 2. Nf3
+        let history = history(&[(12, 29), (20, 37), (6, 22)]);
+
+        assert_eq!(
+            history.replay().unwrap().to_fen(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+    }
+
+    #[test]
+    fn test_replay_rejects_illegal_moves() {
+        // 1. e4 e5 2. e5?? into the black pawn
+        let history = history(&[(12, 29), (20, 37), (12, 37)]);
+
+        assert!(history.replay().is_err());
+    }
+
+    #[test]
+    fn test_longest_fen_fits() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 65535 65535";
+        assert!(Position::from_fen(fen).unwrap().to_fen().len() <= MAX_FEN_LEN);
+        assert_eq!(MoveHistory::space(2, 1), MoveHistory::space(0, 0) + 12);
+    }
+
+    #[test]
+    fn test_repetitions() {
+        let mut position = Position::from_fen(START_FEN).unwrap();
+        let mut history = history(&[]);
+        history.positions.push(position.zobrist_hash());
+        assert_eq!(history.repetitions(), 1);
+
+        // 1. e4, then knights out and back twice
+        let knights = [(30, 46), (6, 22), (30, 63), (6, 7)];
+        for mv in std::iter::once((12, 29)).chain(knights).chain(knights) {
+            let mv = play(mv.0, mv.1);
+            apply_move(&mut position, mv.piece, mv.destination, None);
+            history.record(mv, &position);
+        }
+
+        // The position after 1. e4 came back twice, the pawn move dropped the start
+        assert_eq!(history.moves.len(), 9);
+        assert_eq!(history.positions.len(), 9);
+        assert_eq!(history.repetitions(), 3);
+
+        // 2... Nf6 a third time
+        let mv = play(30, 46);
+        apply_move(&mut position, mv.piece, mv.destination, None);
+        history.record(mv, &position);
+        assert_eq!(history.repetitions(), 3);
+
+        // 3. d4 starts afresh
+        let mv = play(11, 28);
+        apply_move(&mut position, mv.piece, mv.destination, None);
+        history.record(mv, &position);
+        assert_eq!(history.positions.len(), 1);
+        assert_eq!(history.repetitions(), 1);
+    }
+
+    #[test]
+    fn test_space_for_next_move_never_shrinks() {
+        let mut history = history(&[(12, 29), (20, 37)]);
+        history.positions = vec![1, 2];
+        let grown = history.space_for_next_move(MoveHistory::space(2, 2));
+        assert_eq!(grown, MoveHistory::space(3, 3));
+
+        // A pawn move cleared the hashes: the freed room is reused, not refunded
+        history.positions = vec![3];
+        assert_eq!(history.space_for_next_move(grown), grown);
+        history.positions = vec![3, 4];
+        assert_eq!(history.space_for_next_move(grown), grown);
+        history.positions = vec![3, 4, 5];
+        assert_eq!(history.space_for_next_move(grown), grown + 8);
+    }
+}
@@ -6,13 +6,37 @@
 #![allow(deprecated)]
 
 use anchor_lang::prelude::*;
+use anchor_lang::system_program::{transfer, Transfer};
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_interface::spl_token_2022::extension::{
+    BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+};
+use anchor_spl::token_interface::{
+    close_account, spl_token_2022, transfer_checked, CloseAccount, Mint, TokenAccount,
+    TokenInterface, TransferChecked,
+};
 
-mod board;
-mod error;
-mod game_logic;
+pub mod board;
+pub mod challenge;
+pub mod config;
+pub mod error;
+mod events;
+pub mod game_logic;
+pub mod history;
+pub mod pgn;
+pub mod profile;
 
-use crate::board::Board;
+use crate::board::{
+    Board, GameResult, Promotion, Termination, TimeControl, DEFAULT_INACTIVITY_WINDOW,
+};
+use crate::challenge::Challenge;
+use crate::config::Config;
 use crate::error::ChessError;
+use crate::events::*;
+use crate::game_logic::fen::START_FEN;
+use crate::game_logic::{Move as ChessMove, PieceId, Position, Square};
+use crate::history::MoveHistory;
+use crate::profile::PlayerProfile;
 
 declare_id!("31xiptEVG9npfKRzuToPsBGwrBs6tSw5bRj6VhSnMgWH");
 
@@ -20,69 +44,350 @@ declare_id!("31xiptEVG9npfKRzuToPsBGwrBs6tSw5bRj6VhSnMgWH");
 pub mod anchor_chess {
     use super::*;
 
+    /// Creates the program's `Config`, once and only by the program's upgrade authority,
+    /// who becomes its admin
+    pub fn initialize_config(ctx: Context<InitializeConfig>, fee_bps: u16) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.bump = ctx.bumps.config;
+        config.paused = false;
+        config.set_fee(fee_bps, ctx.accounts.treasury.key())?;
+
+        emit_config_updated(config);
+
+        Ok(())
+    }
+
+    /// Admin only: changes the fee taken from the pots of games created from now on,
+    /// and where it goes
+    pub fn update_config(ctx: Context<UpdateConfig>, fee_bps: u16) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.set_fee(fee_bps, ctx.accounts.treasury.key())?;
+
+        emit_config_updated(config);
+
+        Ok(())
+    }
+
+    /// Admin only: stops or resumes the creation, listing and joining of games
+    pub fn set_paused(ctx: Context<ConfigAdmin>, paused: bool) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.paused = paused;
+
+        emit_config_updated(config);
+
+        Ok(())
+    }
+
+    /// Admin only: hands the config over to a new admin
+    pub fn set_admin(ctx: Context<ConfigAdmin>, admin: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.admin = admin;
+
+        emit_config_updated(config);
+
+        Ok(())
+    }
+
     /// Initializes chess board on-chain
     /// Maker has always white pieces
-    pub fn initialize(ctx: Context<Initialize>, seed: u64, guest: Option<Pubkey>) -> Result<()> {
-        let board = Board::new(ctx.bumps.board, seed, guest, ctx.accounts.maker.key());
+    /// `time_control` is `None` for untimed games
+    /// `inactivity_window` (seconds) bounds how long an untimed game may sit idle,
+    /// defaults to `DEFAULT_INACTIVITY_WINDOW`
+    /// `wager` is the amount each player stakes, deposited now by the maker
+    /// and by the guest when joining. Wagered games can't name their guest upfront.
+    /// The wager is in lamports, or in tokens of `mint` when given.
+    /// `rated` games update both players' `PlayerProfile`, which must exist.
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        seed: u64,
+        guest: Option<Pubkey>,
+        time_control: Option<TimeControl>,
+        inactivity_window: Option<u32>,
+        wager: u64,
+        rated: bool,
+    ) -> Result<()> {
+        let bumps = (ctx.bumps.board, ctx.bumps.history);
+        ctx.accounts.create_board(
+            bumps,
+            seed,
+            guest,
+            time_control,
+            inactivity_window,
+            wager,
+            rated,
+            None,
+        )
+    }
 
-        ctx.accounts.board.set_inner(board);
+    /// Like `initialize`, but the game starts from a custom position
+    /// given as FEN, e.g. for endgame drills, opening lines or odds games.
+    /// These games are never rated.
+    pub fn initialize_from_position(
+        ctx: Context<Initialize>,
+        seed: u64,
+        guest: Option<Pubkey>,
+        time_control: Option<TimeControl>,
+        inactivity_window: Option<u32>,
+        wager: u64,
+        fen: String,
+    ) -> Result<()> {
+        let position = Position::from_fen(&fen)?;
+        game_logic::validate_setup(&position)?;
+
+        let bumps = (ctx.bumps.board, ctx.bumps.history);
+        ctx.accounts.create_board(
+            bumps,
+            seed,
+            guest,
+            time_control,
+            inactivity_window,
+            wager,
+            false,
+            Some(position),
+        )
+    }
+
+    /// Creates the signer's `PlayerProfile`, needed to play rated games
+    pub fn create_profile(ctx: Context<CreateProfile>) -> Result<()> {
+        let profile = PlayerProfile::new(
+            ctx.accounts.player.key(),
+            ctx.bumps.profile,
+            Clock::get()?.unix_timestamp,
+        );
+        ctx.accounts.profile.set_inner(profile);
+
+        Ok(())
+    }
+
+    /// Lists a board still waiting for its guest in the lobby.
+    /// `allowed_guests` limits who may join (anyone when empty),
+    /// `min_profile_age` (seconds) how long ago the guest's `PlayerProfile` must have been created
+    pub fn open_challenge(
+        ctx: Context<OpenChallenge>,
+        allowed_guests: Vec<Pubkey>,
+        min_profile_age: u32,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, ChessError::Paused);
+        let board = &mut ctx.accounts.board;
+        let challenge = Challenge::new(
+            ctx.bumps.challenge,
+            board.key(),
+            board,
+            allowed_guests,
+            min_profile_age,
+            Clock::get()?.unix_timestamp,
+        )?;
+        board.in_lobby = true;
+
+        emit!(ChallengeOpened {
+            board: board.key(),
+            maker: board.maker,
+            allowed_guests: challenge.allowed_guests.clone(),
+        });
+        ctx.accounts.challenge.set_inner(challenge);
+
+        Ok(())
+    }
+
+    /// Takes the board out of the lobby, anyone may then join it again
+    pub fn cancel_challenge(ctx: Context<CancelChallenge>) -> Result<()> {
+        let board = &mut ctx.accounts.board;
+        board.in_lobby = false;
+
+        emit!(ChallengeClosed {
+            board: board.key(),
+            guest: None,
+        });
 
         Ok(())
     }
 
     /// Optional: the guest joins in a second moment.
-    /// Guest joins chess board
+    /// Guest joins chess board, depositing the same wager as the maker.
+    /// `guest` must be the signer.
+    /// Boards in the lobby only admit the guests their `Challenge` allows.
     pub fn join(ctx: Context<Join>, guest: Pubkey) -> Result<()> {
+        require!(!ctx.accounts.config.paused, ChessError::Paused);
         let board = &mut ctx.accounts.board;
 
         require!(board.guest.is_none(), ChessError::GuestAlreadyPresent);
+        // A board resigned before anyone joined is cancelled, its stake only goes back
+        require!(!board.is_over(), ChessError::GameAlreadyOver);
         require!(ctx.accounts.maker.key() != guest, ChessError::InvalidPlayer);
+        // The deposit, the profile and the challenge's restrictions are all the signer's
+        require_keys_eq!(guest, ctx.accounts.guest.key(), ChessError::InvalidPlayer);
+
+        if board.in_lobby {
+            let challenge = ctx
+                .accounts
+                .challenge
+                .as_ref()
+                .ok_or(ChessError::ChallengeMissing)?;
+            let profile_created_at = ctx
+                .accounts
+                .guest_profile
+                .as_ref()
+                .map(|profile| profile.created_at);
+            challenge.admits(guest, profile_created_at, Clock::get()?.unix_timestamp)?;
+
+            // Closed along by Anchor, the game is no longer open
+            board.in_lobby = false;
+            emit!(ChallengeClosed {
+                board: board.key(),
+                guest: Some(guest),
+            });
+        }
+
+        // Rated games must be able to rate the guest in the end
+        require!(
+            !board.rated || ctx.accounts.guest_profile.is_some(),
+            ChessError::ProfileMissing
+        );
 
         board.guest = Some(guest);
+        board.start_clock(Clock::get()?.unix_timestamp);
+
+        if board.wager > 0 {
+            let guest_account = ctx.accounts.guest.to_account_info();
+            match board.wager_mint {
+                None => {
+                    let deposit = CpiContext::new(
+                        ctx.accounts.system_program.to_account_info(),
+                        Transfer {
+                            from: guest_account,
+                            to: board.to_account_info(),
+                        },
+                    );
+                    transfer(deposit, board.wager)?;
+                }
+                Some(_) => transfer_tokens(
+                    &ctx.accounts.token_program,
+                    &ctx.accounts.mint,
+                    &ctx.accounts.guest_tokens,
+                    &ctx.accounts.vault,
+                    guest_account,
+                    &[],
+                    board.wager,
+                )?,
+            }
+            board.deposit_wager();
+        }
+
+        emit!(GuestJoined {
+            board: board.key(),
+            guest,
+        });
 
         Ok(())
     }
 
-    pub fn move_piece(ctx: Context<Move>, piece_idx: u8, destination: u8) -> Result<()> {
+    /// Moves one of the player's pieces.
+    /// `piece_idx` is the piece's slot in `Board::state` (0..32),
+    /// `destination` a square numbered like the board state (1..=64).
+    /// `promotion` names the new piece when a pawn reaches the last rank.
+    pub fn move_piece(
+        ctx: Context<Move>,
+        piece_idx: u8,
+        destination: u8,
+        promotion: Option<Promotion>,
+    ) -> Result<()> {
         // --- Bounds check ---
-        require!(piece_idx < 32 && destination <= 64, ChessError::OutOfBounds);
+        let piece = PieceId::try_from(piece_idx)?;
+        let destination = Square::try_from(destination)?;
 
         let board = &mut ctx.accounts.board;
         let player_key = ctx.accounts.player.key();
 
-        // --- Ensure both players are present ---
+        // --- Ensure both players are present and the game is still running ---
         require!(board.guest.is_some(), ChessError::GuestPlayerNotPresent);
+        require!(!board.is_over(), ChessError::GameAlreadyOver);
 
         let is_valid = if board.is_white_turn {
             // White’s turn: must be maker, and piece must be in 0..16
-            player_key == board.maker && piece_idx < 16
+            player_key == board.maker && piece.is_white()
         } else {
             // Black’s turn: must be guest, and piece must be in 16..32
-            Some(player_key) == board.guest && piece_idx >= 16
+            Some(player_key) == board.guest && !piece.is_white()
         };
 
         require!(is_valid, ChessError::InvalidPlayer);
 
+        // --- Charge the thinking time, rejecting moves after a flag fall ---
+        board.punch_clock(Clock::get()?.unix_timestamp)?;
+
         // --- Validate move legality ---
-        let current_pos = board.state[piece_idx as usize];
-        let move_legal =
-            game_logic::is_move_legal(current_pos, piece_idx, destination, &board.state);
-        msg!("{:?}, {:?}, {:?}", current_pos, piece_idx, destination);
+        let mut position = board.position();
+        let origin = position.square_of(piece);
+        let move_legal = game_logic::is_move_legal(&position, piece, destination);
         require!(move_legal?, ChessError::IllegalMove);
 
-        // --- Capture any opposite color piece at the destination ---
-        let mut target_range = if board.is_white_turn { 16..32 } else { 0..16 };
-        if let Some(capture_idx) = target_range.find(|&idx| board.state[idx] == destination) {
-            board.state[capture_idx] = 0; // captured
-        }
+        // --- The mover's king must not be left attacked ---
+        require!(
+            !game_logic::leaves_king_in_check(&position, piece, destination),
+            ChessError::KingInCheck
+        );
+
+        // --- A pawn on the last rank must be promoted ---
+        let promotion = promotion.map(game_logic::PieceType::from);
+        game_logic::validate_promotion(&position, piece, destination, promotion)?;
 
-        // --- Move the piece ---
-        board.state[piece_idx as usize] = destination;
+        // --- Move the piece, capturing any opposite color piece (en passant included),
+        //     and swap turn ---
+        let captured = game_logic::apply_move(&mut position, piece, destination, promotion);
+        board.set_position(position);
 
-        // --- Swap turn ---
-        board.is_white_turn = !board.is_white_turn;
+        // --- Record it, the history account was grown by one entry for it ---
+        let played = ChessMove {
+            piece,
+            destination,
+            promotion,
+        };
+        ctx.accounts.history.record(played, &position);
 
-        // TODO: count points, update game state, emit events, etc.
+        emit!(MoveMade {
+            board: board.key(),
+            player: player_key,
+            piece: piece.into(),
+            from: origin.map_or(0, u8::from),
+            to: destination.into(),
+            promotion: promotion.and_then(|piece| Promotion::try_from(piece).ok()),
+            capture: captured.is_some(),
+            check: game_logic::is_in_check(board.is_white_turn, &position),
+        });
+
+        // --- Moving again withdraws the player's own draw offer ---
+        if board.draw_offer == Some(player_key) {
+            board.draw_offer = None;
+        }
+
+        // --- End the game if the opponent has no legal reply, nobody can mate anymore,
+        //     or after 75 moves without a capture or pawn move ---
+        let outcome = if !game_logic::has_legal_move(&position) {
+            if game_logic::is_in_check(board.is_white_turn, &position) {
+                // The side that just moved delivered mate
+                let result = GameResult::win_for(!board.is_white_turn);
+                Some((result, Termination::Checkmate))
+            } else {
+                Some((GameResult::Draw, Termination::Stalemate))
+            }
+        } else if game_logic::is_insufficient_material(&position) {
+            Some((GameResult::Draw, Termination::InsufficientMaterial))
+        } else if position.halfmove_clock >= game_logic::SEVENTY_FIVE_MOVE_RULE {
+            Some((GameResult::Draw, Termination::FiftyMoves))
+        } else {
+            None
+        };
+        if let Some((result, termination)) = outcome {
+            board.finish(result, termination);
+            emit_game_ended(board);
+            rate_game(
+                board,
+                &mut ctx.accounts.white_profile,
+                &mut ctx.accounts.black_profile,
+            )?;
+        }
 
         Ok(())
     }
@@ -92,6 +397,135 @@ pub mod anchor_chess {
         let board = &mut ctx.accounts.board;
         let player_key = ctx.accounts.player.key();
         board.resign(player_key)?;
+        emit_game_ended(board);
+        rate_game(
+            board,
+            &mut ctx.accounts.white_profile,
+            &mut ctx.accounts.black_profile,
+        )?;
+
+        Ok(())
+    }
+
+    /// Win a game whose opponent ran out of time or abandoned it
+    pub fn claim_timeout(ctx: Context<ClaimTimeout>) -> Result<()> {
+        let board = &mut ctx.accounts.board;
+        let player_key = ctx.accounts.player.key();
+        board.claim_timeout(player_key, Clock::get()?.unix_timestamp)?;
+        emit_game_ended(board);
+        rate_game(
+            board,
+            &mut ctx.accounts.white_profile,
+            &mut ctx.accounts.black_profile,
+        )?;
+
+        Ok(())
+    }
+
+    /// Offer a draw to the opponent
+    pub fn offer_draw(ctx: Context<DrawOffer>) -> Result<()> {
+        let board = &mut ctx.accounts.board;
+        let player_key = ctx.accounts.player.key();
+        board.offer_draw(player_key)?;
+
+        emit!(DrawOffered {
+            board: board.key(),
+            player: player_key,
+        });
+
+        Ok(())
+    }
+
+    /// Accept the opponent's draw offer, ending the game as a draw
+    pub fn accept_draw(ctx: Context<AcceptDraw>) -> Result<()> {
+        let board = &mut ctx.accounts.board;
+        board.accept_draw(ctx.accounts.player.key())?;
+        emit_game_ended(board);
+        rate_game(
+            board,
+            &mut ctx.accounts.white_profile,
+            &mut ctx.accounts.black_profile,
+        )?;
+
+        Ok(())
+    }
+
+    /// Draw a game whose current position has occurred three times,
+    /// or after fifty moves without a capture or pawn move
+    pub fn claim_draw(ctx: Context<ClaimDraw>) -> Result<()> {
+        let board = &mut ctx.accounts.board;
+        let repetitions = ctx.accounts.history.repetitions();
+        board.claim_draw(ctx.accounts.player.key(), repetitions)?;
+        emit_game_ended(board);
+        rate_game(
+            board,
+            &mut ctx.accounts.white_profile,
+            &mut ctx.accounts.black_profile,
+        )?;
+
+        Ok(())
+    }
+
+    /// Decline the opponent's draw offer
+    pub fn decline_draw(ctx: Context<DrawOffer>) -> Result<()> {
+        let board = &mut ctx.accounts.board;
+        board.decline_draw(ctx.accounts.player.key())
+    }
+
+    /// Pay out the wagers of a finished game: the pot to the winner less the board's fee,
+    /// which goes to the treasury, or each stake back on a draw. Anyone may call it.
+    pub fn settle(ctx: Context<Settle>) -> Result<()> {
+        let payout = ctx.accounts.board.settle_wager()?;
+        let accounts = &ctx.accounts;
+        let board = &accounts.board;
+
+        if board.wager_mint.is_some() {
+            // The vault pays out, signed for by the board
+            let seed = board.seed.to_le_bytes();
+            let signer_seeds: &[&[&[u8]]] =
+                &[&[b"board", board.maker.as_ref(), &seed, &[board.bump]]];
+            let pay = |to, amount| {
+                transfer_tokens(
+                    &accounts.token_program,
+                    &accounts.mint,
+                    &accounts.vault,
+                    to,
+                    board.to_account_info(),
+                    signer_seeds,
+                    amount,
+                )
+            };
+            if payout.maker > 0 {
+                pay(&accounts.maker_tokens, payout.maker)?;
+            }
+            if payout.guest > 0 {
+                pay(&accounts.guest_tokens, payout.guest)?;
+            }
+            if payout.fee > 0 {
+                pay(&accounts.treasury_tokens, payout.fee)?;
+            }
+        } else {
+            if payout.maker > 0 {
+                board.sub_lamports(payout.maker)?;
+                accounts.maker.add_lamports(payout.maker)?;
+            }
+            if payout.guest > 0 {
+                let guest = accounts.guest.as_ref().ok_or(ChessError::InvalidPlayer)?;
+                board.sub_lamports(payout.guest)?;
+                guest.add_lamports(payout.guest)?;
+            }
+            if payout.fee > 0 {
+                board.sub_lamports(payout.fee)?;
+                accounts.treasury.add_lamports(payout.fee)?;
+            }
+        }
+
+        emit!(WagerSettled {
+            board: board.key(),
+            maker_share: payout.maker,
+            guest_share: payout.guest,
+            fee: payout.fee,
+        });
 
         Ok(())
     }
@@ -99,14 +533,216 @@ pub mod anchor_chess {
     /// Close the board account
     pub fn close(ctx: Context<Close>) -> Result<()> {
         let board = &ctx.accounts.board;
-        require!(board.game_over, ChessError::CannotCloseMatch);
+        require!(board.is_over(), ChessError::CannotCloseMatch);
+        // The maker would otherwise walk away with the escrowed stakes
+        require!(board.pot == 0, ChessError::WagerNotSettled);
+        // Leave no challenge behind in the lobby
+        require!(
+            !board.in_lobby || ctx.accounts.challenge.is_some(),
+            ChessError::ChallengeMissing
+        );
+
+        // Give the empty vault's rent back too
+        if board.wager_mint.is_some() {
+            let (Some(vault), Some(token_program)) =
+                (&ctx.accounts.vault, &ctx.accounts.token_program)
+            else {
+                return err!(ChessError::TokenAccountsMissing);
+            };
+            let seed = board.seed.to_le_bytes();
+            let signer_seeds: &[&[&[u8]]] =
+                &[&[b"board", board.maker.as_ref(), &seed, &[board.bump]]];
+            // Anyone can send tokens to the vault, which would keep it from closing
+            if vault.amount > 0 {
+                transfer_tokens(
+                    &ctx.accounts.token_program,
+                    &ctx.accounts.mint,
+                    &ctx.accounts.vault,
+                    &ctx.accounts.maker_tokens,
+                    board.to_account_info(),
+                    signer_seeds,
+                    vault.amount,
+                )?;
+            }
+            close_account(CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                CloseAccount {
+                    account: vault.to_account_info(),
+                    destination: ctx.accounts.maker.to_account_info(),
+                    authority: board.to_account_info(),
+                },
+                signer_seeds,
+            ))?;
+        }
+
+        emit!(BoardClosed {
+            board: board.key(),
+            maker: ctx.accounts.maker.key(),
+        });
+
         // Anchor will automatically transfer lamports back to `maker` and close account
         Ok(())
     }
 }
 
+/// Moves `amount` of a token wager from `from` to `to`, signed by `authority`
+/// (with the board's seeds when paying out of its vault)
+fn transfer_tokens<'info>(
+    token_program: &Option<Interface<'info, TokenInterface>>,
+    mint: &Option<Box<InterfaceAccount<'info, Mint>>>,
+    from: &Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    to: &Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    authority: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    amount: u64,
+) -> Result<()> {
+    let (Some(token_program), Some(mint), Some(from), Some(to)) = (token_program, mint, from, to)
+    else {
+        return err!(ChessError::TokenAccountsMissing);
+    };
+
+    let accounts = TransferChecked {
+        from: from.to_account_info(),
+        mint: mint.to_account_info(),
+        to: to.to_account_info(),
+        authority,
+    };
+    let cpi = CpiContext::new_with_signer(token_program.to_account_info(), accounts, signer_seeds);
+    transfer_checked(cpi, amount, mint.decimals)
+}
+
+/// Whether a Token-2022 mint can take a fee on transfers, which would leave
+/// the vault holding less than the pot it owes
+fn charges_transfer_fee(mint: &InterfaceAccount<Mint>) -> Result<bool> {
+    let info = mint.to_account_info();
+    let data = info.try_borrow_data()?;
+    let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    Ok(mint
+        .get_extension_types()?
+        .contains(&ExtensionType::TransferFeeConfig))
+}
+
+/// Announces the current settings of `config`
+fn emit_config_updated(config: &Config) {
+    emit!(ConfigUpdated {
+        admin: config.admin,
+        fee_bps: config.fee_bps,
+        treasury: config.treasury,
+        paused: config.paused,
+    });
+}
+
+/// Updates the players' profiles once a rated game `Board::finish` just ended.
+/// Games the guest never joined aren't rated.
+fn rate_game(
+    board: &Account<Board>,
+    white_profile: &mut Option<Box<Account<PlayerProfile>>>,
+    black_profile: &mut Option<Box<Account<PlayerProfile>>>,
+) -> Result<()> {
+    if !board.rated || board.guest.is_none() {
+        return Ok(());
+    }
+    let (Some(white), Some(black)) = (white_profile, black_profile) else {
+        return err!(ChessError::ProfileMissing);
+    };
+
+    profile::rate_game(white, black, board.result);
+
+    emit!(RatingsUpdated {
+        board: board.key(),
+        white_rating: white.rating,
+        black_rating: black.rating,
+    });
+
+    Ok(())
+}
+
+/// Announces a game `Board::finish` just ended
+fn emit_game_ended(board: &Account<Board>) {
+    emit!(GameEnded {
+        board: board.key(),
+        result: board.result,
+        termination: board.termination,
+    });
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        init,
+        payer = admin,
+        space = Config::INIT_SPACE + Config::DISCRIMINATOR.len(),
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+    // The upgrade authority proves who deployed the program
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::AnchorChess>,
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(admin.key())
+            @ ChessError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    /// Receives the fees, funded enough to stay rent exempt when it takes a small one
+    #[account(
+        constraint = Rent::get()?.is_exempt(treasury.lamports(), 0)
+            @ ChessError::TreasuryNotRentExempt
+    )]
+    pub treasury: SystemAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ChessError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+    /// Receives the fees, funded enough to stay rent exempt when it takes a small one
+    #[account(
+        constraint = Rent::get()?.is_exempt(treasury.lamports(), 0)
+            @ ChessError::TreasuryNotRentExempt
+    )]
+    pub treasury: SystemAccount<'info>,
+}
+
 #[derive(Accounts)]
-#[instruction(seed: u64)]
+// shared by set_paused and set_admin
+pub struct ConfigAdmin<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ChessError::Unauthorized,
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct CreateProfile<'info> {
+    #[account(mut)]
+    pub player: Signer<'info>,
+    #[account(
+        init,
+        payer = player,
+        space = PlayerProfile::INIT_SPACE + PlayerProfile::DISCRIMINATOR.len(),
+        seeds = [b"profile", player.key().as_ref()],
+        bump,
+    )]
+    pub profile: Account<'info, PlayerProfile>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(seed: u64, guest: Option<Pubkey>)]
 pub struct Initialize<'info> {
     #[account(mut)]
     pub maker: Signer<'info>,
@@ -118,9 +754,202 @@ pub struct Initialize<'info> {
         bump,
     )]
     pub board: Account<'info, Board>,
+    #[account(
+        init,
+        payer = maker,
+        space = MoveHistory::space(0, 1),
+        seeds = [b"moves", board.key().as_ref()],
+        bump,
+    )]
+    pub history: Account<'info, MoveHistory>,
+    /// Whether games may be created, and the fee they take
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// Rated games only: the players' profiles, the guest's when named upfront
+    #[account(seeds = [b"profile", maker.key().as_ref()], bump = maker_profile.bump)]
+    pub maker_profile: Option<Box<Account<'info, PlayerProfile>>>,
+    #[account(
+        seeds = [b"profile", guest.unwrap_or_default().as_ref()],
+        bump = guest_profile.bump,
+    )]
+    pub guest_profile: Option<Box<Account<'info, PlayerProfile>>>,
+    /// Mint of a token wager, left out to wager lamports
+    pub mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+    /// The maker's tokens, to deposit the wager from
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = maker,
+        token::token_program = token_program,
+    )]
+    pub maker_tokens: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// Escrow of a token wager, the board's associated token account
+    #[account(
+        init,
+        payer = maker,
+        associated_token::mint = mint,
+        associated_token::authority = board,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    pub associated_token_program: Option<Program<'info, AssociatedToken>>,
+    pub system_program: Program<'info, System>,
+}
+
+impl Initialize<'_> {
+    /// Stores a new board with an empty move history,
+    /// starting from `start` or the usual starting position,
+    /// and escrows the maker's wager
+    #[allow(clippy::too_many_arguments)]
+    fn create_board(
+        &mut self,
+        (bump, history_bump): (u8, u8),
+        seed: u64,
+        guest: Option<Pubkey>,
+        time_control: Option<TimeControl>,
+        inactivity_window: Option<u32>,
+        wager: u64,
+        rated: bool,
+        start: Option<Position>,
+    ) -> Result<()> {
+        require!(!self.config.paused, ChessError::Paused);
+        if let Some(time_control) = time_control {
+            time_control.validate()?;
+        }
+        let inactivity_window = inactivity_window.map_or(DEFAULT_INACTIVITY_WINDOW, i64::from);
+        require!(inactivity_window > 0, ChessError::InvalidTimeControl);
+        // A seated guest would play without staking anything
+        require!(wager == 0 || guest.is_none(), ChessError::InvalidWager);
+        require!(wager > 0 || self.mint.is_none(), ChessError::InvalidWager);
+        if let Some(mint) = &self.mint {
+            require!(!charges_transfer_fee(mint)?, ChessError::TransferFeeMint);
+        }
+        // Both players need a profile for the game to be rated
+        require!(
+            !rated
+                || (self.maker_profile.is_some()
+                    && (guest.is_none() || self.guest_profile.is_some())),
+            ChessError::ProfileMissing
+        );
+
+        let mut board = Board::new(
+            bump,
+            seed,
+            guest,
+            self.maker.key(),
+            time_control,
+            inactivity_window,
+        );
+        if guest.is_some() {
+            board.start_clock(Clock::get()?.unix_timestamp);
+        }
+        if let Some(position) = start {
+            board.set_position(position);
+        }
+        board.rated = rated;
+        if wager > 0 {
+            // Later fee changes don't touch running games
+            board.fee_bps = self.config.fee_bps;
+            match &self.mint {
+                None => {
+                    let deposit = CpiContext::new(
+                        self.system_program.to_account_info(),
+                        Transfer {
+                            from: self.maker.to_account_info(),
+                            to: self.board.to_account_info(),
+                        },
+                    );
+                    transfer(deposit, wager)?;
+                }
+                Some(mint) => {
+                    transfer_tokens(
+                        &self.token_program,
+                        &self.mint,
+                        &self.maker_tokens,
+                        &self.vault,
+                        self.maker.to_account_info(),
+                        &[],
+                        wager,
+                    )?;
+                    board.wager_mint = Some(mint.key());
+                }
+            }
+            board.wager = wager;
+            board.deposit_wager();
+        }
+        let start_fen = start.map_or(START_FEN.to_string(), |position| position.to_fen());
+
+        let start_hash = board.position().zobrist_hash();
+        self.board.set_inner(board);
+        self.history.set_inner(MoveHistory {
+            board: self.board.key(),
+            bump: history_bump,
+            start_fen: start_fen.clone(),
+            moves: Vec::new(),
+            positions: vec![start_hash],
+        });
+
+        emit!(GameCreated {
+            board: self.board.key(),
+            maker: self.maker.key(),
+            guest,
+            seed,
+            time_control,
+            wager,
+            wager_mint: self.mint.as_ref().map(|mint| mint.key()),
+            start_fen,
+        });
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct OpenChallenge<'info> {
+    #[account(mut)]
+    pub maker: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"board", maker.key().as_ref(), board.seed.to_le_bytes().as_ref()],
+        bump = board.bump,
+        has_one = maker,
+    )]
+    pub board: Account<'info, Board>,
+    #[account(
+        init,
+        payer = maker,
+        space = Challenge::INIT_SPACE + Challenge::DISCRIMINATOR.len(),
+        seeds = [b"challenge", board.key().as_ref()],
+        bump,
+    )]
+    pub challenge: Account<'info, Challenge>,
+    /// No new challenges while paused
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct CancelChallenge<'info> {
+    #[account(mut)]
+    pub maker: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"board", maker.key().as_ref(), board.seed.to_le_bytes().as_ref()],
+        bump = board.bump,
+        has_one = maker,
+    )]
+    pub board: Account<'info, Board>,
+    #[account(
+        mut,
+        close = maker,
+        seeds = [b"challenge", board.key().as_ref()],
+        bump = challenge.bump,
+    )]
+    pub challenge: Account<'info, Challenge>,
+}
+
 #[derive(Accounts)]
 pub struct Join<'info> {
     #[account(mut)]
@@ -133,14 +962,48 @@ pub struct Join<'info> {
         has_one = maker
     )]
     pub board: Account<'info, Board>,
-    // read only needed for checking
+    // needed for checking, and gets the rent of a closed challenge back
+    #[account(mut)]
     pub maker: SystemAccount<'info>,
+    /// The board's open challenge, closed once taken up
+    #[account(
+        mut,
+        close = maker,
+        seeds = [b"challenge", board.key().as_ref()],
+        bump = challenge.bump,
+    )]
+    pub challenge: Option<Box<Account<'info, Challenge>>>,
+    /// No games start while paused
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// Rated games and challenges with a minimum profile age only: the guest's profile
+    #[account(seeds = [b"profile", guest.key().as_ref()], bump = guest_profile.bump)]
+    pub guest_profile: Option<Box<Account<'info, PlayerProfile>>>,
+    /// Token wagers only: the wager's mint, the guest's tokens and the board's vault
+    #[account(address = board.wager_mint.unwrap_or_default())]
+    pub mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = guest,
+        token::token_program = token_program,
+    )]
+    pub guest_tokens: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = board,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+    // needed to deposit the wager
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-// system program not required
 pub struct Move<'info> {
-    /// The player making the move
+    /// The player making the move, paying for the history's extra entry
     #[account(mut)]
     pub player: Signer<'info>,
 
@@ -151,6 +1014,33 @@ pub struct Move<'info> {
         bump = board.bump,
     )]
     pub board: Account<'info, Board>,
+
+    /// The board's move history, grown by one entry unless
+    /// room freed by cleared position hashes already fits it
+    #[account(
+        mut,
+        seeds = [b"moves", board.key().as_ref()],
+        bump = history.bump,
+        realloc = history.space_for_next_move(history.to_account_info().data_len()),
+        realloc::payer = player,
+        realloc::zero = false,
+    )]
+    pub history: Account<'info, MoveHistory>,
+    /// Rated games only: both players' profiles, updated when the game ends
+    #[account(
+        mut,
+        seeds = [b"profile", board.maker.as_ref()],
+        bump = white_profile.bump,
+    )]
+    pub white_profile: Option<Box<Account<'info, PlayerProfile>>>,
+    #[account(
+        mut,
+        seeds = [b"profile", board.guest.unwrap_or_default().as_ref()],
+        bump = black_profile.bump,
+    )]
+    pub black_profile: Option<Box<Account<'info, PlayerProfile>>>,
+    // needed by realloc
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
@@ -159,6 +1049,164 @@ pub struct Resign<'info> {
     pub player: Signer<'info>,
     #[account(mut)]
     pub board: Account<'info, Board>,
+    /// Rated games only: both players' profiles, updated when the game ends
+    #[account(
+        mut,
+        seeds = [b"profile", board.maker.as_ref()],
+        bump = white_profile.bump,
+    )]
+    pub white_profile: Option<Box<Account<'info, PlayerProfile>>>,
+    #[account(
+        mut,
+        seeds = [b"profile", board.guest.unwrap_or_default().as_ref()],
+        bump = black_profile.bump,
+    )]
+    pub black_profile: Option<Box<Account<'info, PlayerProfile>>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimTimeout<'info> {
+    pub player: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"board", board.maker.key().as_ref(), board.seed.to_le_bytes().as_ref()],
+        bump = board.bump,
+    )]
+    pub board: Account<'info, Board>,
+    /// Rated games only: both players' profiles, updated when the game ends
+    #[account(
+        mut,
+        seeds = [b"profile", board.maker.as_ref()],
+        bump = white_profile.bump,
+    )]
+    pub white_profile: Option<Box<Account<'info, PlayerProfile>>>,
+    #[account(
+        mut,
+        seeds = [b"profile", board.guest.unwrap_or_default().as_ref()],
+        bump = black_profile.bump,
+    )]
+    pub black_profile: Option<Box<Account<'info, PlayerProfile>>>,
+}
+
+#[derive(Accounts)]
+// shared by offer_draw and decline_draw
+pub struct DrawOffer<'info> {
+    pub player: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"board", board.maker.key().as_ref(), board.seed.to_le_bytes().as_ref()],
+        bump = board.bump,
+    )]
+    pub board: Account<'info, Board>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptDraw<'info> {
+    pub player: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"board", board.maker.key().as_ref(), board.seed.to_le_bytes().as_ref()],
+        bump = board.bump,
+    )]
+    pub board: Account<'info, Board>,
+    /// Rated games only: both players' profiles, updated when the game ends
+    #[account(
+        mut,
+        seeds = [b"profile", board.maker.as_ref()],
+        bump = white_profile.bump,
+    )]
+    pub white_profile: Option<Box<Account<'info, PlayerProfile>>>,
+    #[account(
+        mut,
+        seeds = [b"profile", board.guest.unwrap_or_default().as_ref()],
+        bump = black_profile.bump,
+    )]
+    pub black_profile: Option<Box<Account<'info, PlayerProfile>>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimDraw<'info> {
+    pub player: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"board", board.maker.key().as_ref(), board.seed.to_le_bytes().as_ref()],
+        bump = board.bump,
+    )]
+    pub board: Account<'info, Board>,
+    /// Positions played so far, to count repetitions
+    #[account(
+        seeds = [b"moves", board.key().as_ref()],
+        bump = history.bump,
+    )]
+    pub history: Account<'info, MoveHistory>,
+    /// Rated games only: both players' profiles, updated when the game ends
+    #[account(
+        mut,
+        seeds = [b"profile", board.maker.as_ref()],
+        bump = white_profile.bump,
+    )]
+    pub white_profile: Option<Box<Account<'info, PlayerProfile>>>,
+    #[account(
+        mut,
+        seeds = [b"profile", board.guest.unwrap_or_default().as_ref()],
+        bump = black_profile.bump,
+    )]
+    pub black_profile: Option<Box<Account<'info, PlayerProfile>>>,
+}
+
+#[derive(Accounts)]
+pub struct Settle<'info> {
+    #[account(
+        mut,
+        seeds = [b"board", maker.key().as_ref(), board.seed.to_le_bytes().as_ref()],
+        bump = board.bump,
+        has_one = maker,
+    )]
+    pub board: Account<'info, Board>,
+    #[account(mut)]
+    pub maker: SystemAccount<'info>,
+    /// Only needed when the guest gets a share
+    #[account(mut, address = board.guest.unwrap_or_default())]
+    pub guest: Option<SystemAccount<'info>>,
+    /// Token wagers only: the wager's mint, the board's vault
+    /// and the token accounts of the players getting a share
+    #[account(address = board.wager_mint.unwrap_or_default())]
+    pub mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = board,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = maker,
+        token::token_program = token_program,
+    )]
+    pub maker_tokens: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program,
+        constraint = board.guest == Some(guest_tokens.owner) @ ChessError::InvalidPlayer,
+    )]
+    pub guest_tokens: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// Receives the fee of lamport wagers
+    #[account(mut, address = config.treasury)]
+    pub treasury: SystemAccount<'info>,
+    /// Receives the fee of token wagers
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = config.treasury,
+        token::token_program = token_program,
+    )]
+    pub treasury_tokens: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
 }
 
 #[derive(Accounts)]
@@ -172,4 +1220,39 @@ pub struct Close<'info> {
         bump = board.bump
     )]
     pub board: Box<Account<'info, Board>>,
+    #[account(
+        mut,
+        close = maker,
+        seeds = [b"moves", board.key().as_ref()],
+        bump = history.bump
+    )]
+    pub history: Account<'info, MoveHistory>,
+    /// The board's challenge, if still open, closed along
+    #[account(
+        mut,
+        close = maker,
+        seeds = [b"challenge", board.key().as_ref()],
+        bump = challenge.bump,
+    )]
+    pub challenge: Option<Box<Account<'info, Challenge>>>,
+    /// Token wagers only: the board's vault, closed along
+    #[account(
+        mut,
+        associated_token::mint = board.wager_mint.unwrap_or_default(),
+        associated_token::authority = board,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    /// Needed when tokens were sent to the settled vault: the wager's mint
+    /// and the maker's tokens, which take them back
+    #[account(address = board.wager_mint.unwrap_or_default())]
+    pub mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = maker,
+        token::token_program = token_program,
+    )]
+    pub maker_tokens: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
 }
@@ -0,0 +1,252 @@
+//! PGN export of games, for use off-chain: the moves are read back from the board's
+//! `move_piece` transactions (see `move_from_instruction`) or its `MoveHistory`
+use crate::board::{Board, GameResult, Termination};
+use crate::game_logic::fen::START_FEN;
+use crate::game_logic::notation::to_san;
+use crate::game_logic::Square;
+use crate::game_logic::{apply_move, generate_legal_moves, Move, PieceId, PieceType, Position};
+use crate::instruction::MovePiece;
+use crate::ChessError;
+use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+
+/// PGN lines should stay below 80 characters
+const MAX_LINE_LEN: usize = 79;
+
+/// Decodes the move of a `move_piece` instruction from its data,
+/// `None` for any other instruction
+pub fn move_from_instruction(data: &[u8]) -> Option<Move> {
+    let args = data.strip_prefix(MovePiece::DISCRIMINATOR)?;
+    let MovePiece {
+        piece_idx,
+        destination,
+        promotion,
+    } = MovePiece::try_from_slice(args).ok()?;
+
+    Some(Move {
+        piece: PieceId::try_from(piece_idx).ok()?,
+        destination: Square::try_from(destination).ok()?,
+        promotion: promotion.map(PieceType::from),
+    })
+}
+
+/// Writes the game as PGN, replaying `moves` from `start_fen` and failing on an illegal one.
+/// `started_at` is the unix time of the game's first transaction, when known.
+pub fn export(
+    board: &Board,
+    start_fen: &str,
+    moves: &[Move],
+    started_at: Option<i64>,
+) -> Result<String> {
+    let result = match board.result {
+        GameResult::Ongoing => "*",
+        GameResult::WhiteWins => "1-0",
+        GameResult::BlackWins => "0-1",
+        GameResult::Draw => "1/2-1/2",
+    };
+    let termination = match board.termination {
+        None => "unterminated",
+        Some(Termination::Timeout) => "time forfeit",
+        Some(Termination::Abandonment) => "abandoned",
+        Some(_) => "normal",
+    };
+
+    let mut tags = vec![
+        ("Event", "anchor-chess game".to_string()),
+        ("Site", "Solana".to_string()),
+        (
+            "Date",
+            started_at.map_or("????.??.??".to_string(), pgn_date),
+        ),
+        ("Round", "-".to_string()),
+        ("White", board.maker.to_string()),
+        (
+            "Black",
+            board
+                .guest
+                .map_or("?".to_string(), |guest| guest.to_string()),
+        ),
+        ("Result", result.to_string()),
+        ("Seed", board.seed.to_string()),
+        ("Termination", termination.to_string()),
+    ];
+    if start_fen != START_FEN {
+        tags.push(("SetUp", "1".to_string()));
+        tags.push(("FEN", start_fen.to_string()));
+    }
+
+    let mut position = Position::from_fen(start_fen)?;
+    let mut tokens = Vec::new();
+    for (ply, mv) in moves.iter().enumerate() {
+        require!(
+            generate_legal_moves(&position).contains(mv),
+            ChessError::IllegalMove
+        );
+
+        if position.is_white_turn {
+            tokens.push(format!("{}.", position.fullmove_number));
+        } else if ply == 0 {
+            tokens.push(format!("{}...", position.fullmove_number));
+        }
+        tokens.push(to_san(&position, mv));
+
+        apply_move(&mut position, mv.piece, mv.destination, mv.promotion);
+    }
+    tokens.push(result.to_string());
+
+    let mut pgn: String = tags
+        .iter()
+        .map(|(name, value)| format!("[{name} \"{value}\"]\n"))
+        .collect();
+    pgn.push('\n');
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE_LEN {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    pgn.push_str(&line);
+    pgn.push('\n');
+
+    Ok(pgn)
+}
+
+/// `YYYY.MM.DD` of a unix timestamp, in UTC
+fn pgn_date(timestamp: i64) -> String {
+    // Days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = timestamp.div_euclid(24 * 60 * 60) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::DEFAULT_INACTIVITY_WINDOW;
+    use anchor_lang::InstructionData;
+
+    fn board(result: GameResult, termination: Option<Termination>) -> Board {
+        let mut board = Board::new(
+            255,
+            42,
+            Some(Pubkey::new_unique()),
+            Pubkey::new_unique(),
+            None,
+            DEFAULT_INACTIVITY_WINDOW,
+        );
+        board.result = result;
+        board.termination = termination;
+        board
+    }
+
+    fn play(piece: u8, destination: u8) -> Move {
+        Move {
+            piece: PieceId::try_from(piece).unwrap(),
+            destination: Square::try_from(destination).unwrap(),
+            promotion: None,
+        }
+    }
+
+    #[test]
+    fn test_scholars_mate() {
+        let board = board(GameResult::WhiteWins, Some(Termination::Checkmate));
+        // 1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7#
+        let moves = [
+            play(12, 29),
+            play(20, 37),
+            play(5, 27),
+            play(25, 43),
+            play(3, 40),
+            play(30, 46),
+            play(3, 54),
+        ];
+
+        let pgn = export(&board, START_FEN, &moves, Some(1_700_000_000)).unwrap();
+        let expected = format!(
+            "[Event \"anchor-chess game\"]\n\
+             [Site \"Solana\"]\n\
+             [Date \"2023.11.14\"]\n\
+             [Round \"-\"]\n\
+             [White \"{}\"]\n\
+             [Black \"{}\"]\n\
+             [Result \"1-0\"]\n\
+             [Seed \"42\"]\n\
+             [Termination \"normal\"]\n\
+             \n\
+             1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7# 1-0\n",
+            board.maker,
+            board.guest.unwrap(),
+        );
+        assert_eq!(pgn, expected);
+    }
+
+    #[test]
+    fn test_custom_start_and_long_games() {
+        let board = board(GameResult::Ongoing, None);
+        let fen = "4k3/8/8/8/8/8/8/R3K3 b Q - 0 30";
+
+        // Kings walking back and forth
+        let moves: Vec<Move> = (0..40)
+            .map(|ply| match ply % 4 {
+                0 => play(28, 53),
+                1 => play(4, 13),
+                2 => play(28, 61),
+                _ => play(4, 5),
+            })
+            .collect();
+
+        let pgn = export(&board, fen, &moves, None).unwrap();
+        assert!(pgn.contains("[Date \"????.??.??\"]"));
+        assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/8/R3K3 b Q - 0 30\"]\n"));
+        assert!(pgn.contains("\n30... Ke7 31. Ke2 Ke8 32. Ke1 Ke7"));
+        assert!(pgn.trim_end().ends_with("Ke1 *"));
+        assert!(pgn.lines().all(|line| line.len() <= MAX_LINE_LEN));
+    }
+
+    #[test]
+    fn test_rejects_illegal_moves() {
+        let board = board(GameResult::Ongoing, None);
+        assert!(export(&board, START_FEN, &[play(12, 37)], None).is_err());
+    }
+
+    #[test]
+    fn test_move_from_instruction() {
+        let data = MovePiece {
+            piece_idx: 12,
+            destination: 29,
+            promotion: None,
+        }
+        .data();
+        assert_eq!(move_from_instruction(&data), Some(play(12, 29)));
+
+        let resign = crate::instruction::Resign {}.data();
+        assert_eq!(move_from_instruction(&resign), None);
+    }
+
+    #[test]
+    fn test_pgn_date() {
+        assert_eq!(pgn_date(0), "1970.01.01");
+        assert_eq!(pgn_date(951_782_400), "2000.02.29");
+        assert_eq!(pgn_date(1_700_000_000), "2023.11.14");
+    }
+}
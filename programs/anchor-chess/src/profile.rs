@@ -0,0 +1,181 @@
+use crate::board::GameResult;
+use anchor_lang::prelude::*;
+
+/// Rating of a new player
+pub const DEFAULT_RATING: u16 = 1200;
+/// Ratings never drop below this
+pub const RATING_FLOOR: u16 = 100;
+/// Games a player needs before their rating settles down
+pub const PROVISIONAL_GAMES: u32 = 30;
+
+/// Scores are counted in ten-thousandths: a win, a draw, a loss
+const WIN: i32 = 10_000;
+const DRAW: i32 = 5_000;
+const LOSS: i32 = 0;
+
+/// Step between the rating differences of `EXPECTED_SCORES`
+const DIFFERENCE_STEP: i32 = 25;
+/// Expected score of the higher rated player, `1 / (1 + 10^(-d / 400))`,
+/// for rating differences d = 0, 25, .. 800. Larger gaps count as 800.
+const EXPECTED_SCORES: [i32; 33] = [
+    5000, 5359, 5715, 6063, 6401, 6725, 7034, 7325, 7597, 7850, 8083, 8296, 8490, 8666, 8823, 8965,
+    9091, 9203, 9302, 9390, 9468, 9536, 9595, 9648, 9693, 9733, 9768, 9799, 9825, 9848, 9868, 9886,
+    9901,
+];
+
+/// A player's Elo rating and record across games, at the PDA `["profile", player]`.
+/// Created by the player, then updated whenever one of their rated games ends.
+#[derive(InitSpace)]
+#[account(discriminator = 4)]
+pub struct PlayerProfile {
+    pub player: Pubkey,
+    pub bump: u8,
+    pub rating: u16,
+    /// Rated games played
+    pub games: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+    /// Unix timestamp of the profile's creation, telling established players from fresh wallets
+    pub created_at: i64,
+}
+
+impl PlayerProfile {
+    pub fn new(player: Pubkey, bump: u8, created_at: i64) -> Self {
+        Self {
+            player,
+            bump,
+            rating: DEFAULT_RATING,
+            games: 0,
+            wins: 0,
+            losses: 0,
+            draws: 0,
+            created_at,
+        }
+    }
+
+    /// FIDE-like development coefficient: large while provisional, small for masters
+    fn k_factor(&self) -> i32 {
+        if self.games < PROVISIONAL_GAMES {
+            40
+        } else if self.rating < 2400 {
+            20
+        } else {
+            10
+        }
+    }
+
+    /// Applies a game scored `score` against `opponent_rating`
+    fn record(&mut self, opponent_rating: u16, score: i32) {
+        let expected = expected_score(self.rating, opponent_rating);
+        // Rounded to the nearest point, halves away from zero
+        let change = self.k_factor() * (score - expected);
+        let change = (change + change.signum() * WIN / 2) / WIN;
+        let rating = (i32::from(self.rating) + change).max(i32::from(RATING_FLOOR));
+        self.rating = u16::try_from(rating).unwrap_or(u16::MAX);
+
+        self.games = self.games.saturating_add(1);
+        match score {
+            WIN => self.wins = self.wins.saturating_add(1),
+            LOSS => self.losses = self.losses.saturating_add(1),
+            _ => self.draws = self.draws.saturating_add(1),
+        }
+    }
+}
+
+/// Updates both players' profiles with the result of a finished game,
+/// each from the ratings they had before it
+pub fn rate_game(white: &mut PlayerProfile, black: &mut PlayerProfile, result: GameResult) {
+    let white_score = match result {
+        GameResult::WhiteWins => WIN,
+        GameResult::BlackWins => LOSS,
+        GameResult::Draw => DRAW,
+        GameResult::Ongoing => return,
+    };
+    let (white_rating, black_rating) = (white.rating, black.rating);
+
+    white.record(black_rating, white_score);
+    black.record(white_rating, WIN - white_score);
+}
+
+/// Score `rating` is expected to make against `opponent_rating`, in ten-thousandths,
+/// interpolated between the entries of `EXPECTED_SCORES`
+fn expected_score(rating: u16, opponent_rating: u16) -> i32 {
+    let difference = i32::from(rating) - i32::from(opponent_rating);
+    let gap = difference.abs().min(DIFFERENCE_STEP * 32);
+
+    let index = (gap / DIFFERENCE_STEP) as usize;
+    let low = EXPECTED_SCORES[index];
+    let high = EXPECTED_SCORES[(index + 1).min(32)];
+    let favourite = low + (high - low) * (gap % DIFFERENCE_STEP) / DIFFERENCE_STEP;
+
+    if difference >= 0 {
+        favourite
+    } else {
+        WIN - favourite
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(rating: u16, games: u32) -> PlayerProfile {
+        PlayerProfile {
+            rating,
+            games,
+            ..PlayerProfile::new(Pubkey::new_unique(), 255, 0)
+        }
+    }
+
+    #[test]
+    fn test_expected_score() {
+        assert_eq!(expected_score(1500, 1500), 5000);
+        assert_eq!(expected_score(1900, 1500), 9091);
+        assert_eq!(expected_score(1500, 1900), 909);
+        // Between table entries
+        assert_eq!(expected_score(1510, 1500), 5143);
+        // Capped gap
+        assert_eq!(expected_score(3000, 100), 9901);
+
+        // Never decreasing
+        let scores: Vec<i32> = (0..=1000).map(|d| expected_score(1000 + d, 1000)).collect();
+        assert!(scores.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn test_rate_game() {
+        // Equal provisional players: 40 * 0.5
+        let (mut white, mut black) = (profile(1200, 0), profile(1200, 0));
+        rate_game(&mut white, &mut black, GameResult::WhiteWins);
+        assert_eq!((white.rating, black.rating), (1220, 1180));
+        assert_eq!((white.games, white.wins, white.losses), (1, 1, 0));
+        assert_eq!((black.games, black.wins, black.losses), (1, 0, 1));
+
+        // An even draw changes nothing but the record
+        let (mut white, mut black) = (profile(1500, 50), profile(1500, 50));
+        rate_game(&mut white, &mut black, GameResult::Draw);
+        assert_eq!((white.rating, black.rating), (1500, 1500));
+        assert_eq!((white.draws, black.draws), (1, 1));
+
+        // A draw against a much stronger player gains 20 * (0.5 - 0.0909)
+        let (mut white, mut black) = (profile(1500, 50), profile(1900, 50));
+        rate_game(&mut white, &mut black, GameResult::Draw);
+        assert_eq!((white.rating, black.rating), (1508, 1892));
+
+        // Masters move slower, and the upset still uses the ratings from before the game
+        let (mut white, mut black) = (profile(2500, 100), profile(2100, 10));
+        rate_game(&mut white, &mut black, GameResult::BlackWins);
+        assert_eq!((white.rating, black.rating), (2491, 2136));
+    }
+
+    #[test]
+    fn test_rating_floor() {
+        let (mut white, mut black) = (profile(RATING_FLOOR + 5, 0), profile(RATING_FLOOR, 0));
+        rate_game(&mut white, &mut black, GameResult::BlackWins);
+        assert_eq!(
+            (white.rating, black.rating),
+            (RATING_FLOOR, RATING_FLOOR + 20)
+        );
+    }
+}
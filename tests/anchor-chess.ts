@@ -1,7 +1,22 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { AnchorChess } from "../target/types/anchor_chess";
-import { Keypair, PublicKey, SystemProgram } from "@solana/web3.js";
+import { Keypair, PublicKey, SystemProgram, Transaction } from "@solana/web3.js";
+import {
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  ExtensionType,
+  TOKEN_2022_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
+  createInitializeMintInstruction,
+  createInitializeTransferFeeConfigInstruction,
+  createMint,
+  getAccount,
+  getAssociatedTokenAddressSync,
+  getMintLen,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+  transfer,
+} from "@solana/spl-token";
 import { assert } from "chai";
 import BN from "bn.js";
 
@@ -18,29 +33,118 @@ describe("Minimal chess game example", () => {
   // Seed for PDA derivation
   const seed = new BN(12345);
 
-  // PDA for the board account
-  const [boardPda] = anchor.web3.PublicKey.findProgramAddressSync(
-    [
-      Buffer.from("board"),
-      maker.publicKey.toBuffer(),
-      seed.toArrayLike(Buffer, "le", 8),
-    ],
+  // PDA of a maker's board; the first tests play on the one with `seed`
+  const boardPda = (maker: PublicKey, seed: BN) =>
+    anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("board"), maker.toBuffer(), seed.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+  const gamePda = boardPda(maker.publicKey, seed);
+
+  // Program-wide settings: a 2.5% fee on won pots, paid to the treasury
+  const [configPda] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("config")],
     program.programId
   );
+  const FEE_BPS = 250;
+  const treasury = Keypair.generate();
+
+  // PDA for the board's move history
+  const historyPda = (board: PublicKey) =>
+    anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("moves"), board.toBuffer()],
+      program.programId
+    )[0];
+
+  // Accounts for `initialize` and `initializeFromPosition`: no profiles and no token wager
+  // unless `overrides` names them
+  const initAccounts = (
+    board: PublicKey,
+    overrides: { [account: string]: PublicKey | null } = {}
+  ) => ({
+    maker: maker.publicKey,
+    board,
+    history: historyPda(board),
+    config: configPda,
+    makerProfile: null,
+    guestProfile: null,
+    mint: null,
+    makerTokens: null,
+    vault: null,
+    tokenProgram: null,
+    associatedTokenProgram: null,
+    systemProgram: SystemProgram.programId,
+    ...overrides,
+  });
+
+  it("Initializes the config", async () => {
+    // The treasury must stay rent exempt when it takes a small fee
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(
+        treasury.publicKey,
+        await provider.connection.getMinimumBalanceForRentExemption(0)
+      ),
+      "confirmed"
+    );
+    const unfunded = Keypair.generate().publicKey;
+
+    // Only once per deployment
+    if (await program.account.config.fetchNullable(configPda)) {
+      const updateConfig = (treasury: PublicKey) =>
+        program.methods
+          .updateConfig(FEE_BPS)
+          .accountsStrict({ admin: maker.publicKey, config: configPda, treasury })
+          .rpc({ commitment: "confirmed" });
+      try {
+        await updateConfig(unfunded);
+        assert.fail("An unfunded treasury can't take fees");
+      } catch (err) {
+        assert.include(err.toString(), "TreasuryNotRentExempt");
+      }
+      await updateConfig(treasury.publicKey);
+      return;
+    }
+
+    // Only the upgrade authority may create it
+    const [programData] = PublicKey.findProgramAddressSync(
+      [program.programId.toBuffer()],
+      new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+    );
+    const initializeConfig = (treasury: PublicKey) =>
+      program.methods
+        .initializeConfig(FEE_BPS)
+        .accountsStrict({
+          admin: maker.publicKey,
+          config: configPda,
+          program: program.programId,
+          programData,
+          treasury,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc({ commitment: "confirmed" });
+    try {
+      await initializeConfig(unfunded);
+      assert.fail("An unfunded treasury can't take fees");
+    } catch (err) {
+      assert.include(err.toString(), "TreasuryNotRentExempt");
+    }
+    await initializeConfig(treasury.publicKey);
+
+    const config = await program.account.config.fetch(configPda);
+    assert.ok(config.admin.equals(maker.publicKey));
+    assert.equal(config.feeBps, FEE_BPS);
+    assert.isFalse(config.paused);
+  });
 
   it("Initializes the board", async () => {
     const tx = await program.methods
-      .initialize(seed, null)
-      .accountsStrict({
-        maker: maker.publicKey,
-        board: boardPda,
-        systemProgram: SystemProgram.programId,
-      })
+      .initialize(seed, null, null, null, new BN(0), false)
+      .accountsStrict(initAccounts(gamePda))
       .rpc({ commitment: "confirmed" });
 
     console.log("Initialize tx:", tx);
 
-    const board = await program.account.board.fetch(boardPda);
+    const board = await program.account.board.fetch(gamePda);
     console.log("Board guest:", board.guest?.toBase58());
     assert.equal(
       board.isWhiteTurn,
@@ -54,19 +158,27 @@ describe("Minimal chess game example", () => {
       .join(guest.publicKey)
       .accountsStrict({
         maker: maker.publicKey,
-        board: boardPda,
+        board: gamePda,
         guest: guest.publicKey,
+        guestProfile: null,
+        challenge: null,
+        config: configPda,
+        mint: null,
+        guestTokens: null,
+        vault: null,
+        tokenProgram: null,
+        systemProgram: SystemProgram.programId,
       })
       .rpc({ commitment: "confirmed" });
 
     console.log("Join tx:", tx);
 
-    const board = await program.account.board.fetch(boardPda);
+    const board = await program.account.board.fetch(gamePda);
     console.log("Board guest pubkey:", board.guest?.toBase58());
   });
 
   it("Makes a legal move", async () => {
-    // Example: white pawn from index 8 (a2) to 16 (a3)
+    // Example: white pawn in slot 8 (on a2) to square 17 (a3)
     /*
      *  3 | X . . .    <- destination: a3
      *  2 | P . . .    <- origin: a2
@@ -79,16 +191,20 @@ describe("Minimal chess game example", () => {
     const destination = 17;
 
     const tx = await program.methods
-      .movePiece(pieceIdx, destination)
+      .movePiece(pieceIdx, destination, null)
       .accountsStrict({
         player: maker.publicKey,
-        board: boardPda,
+        board: gamePda,
+        history: historyPda(gamePda),
+        whiteProfile: null,
+        blackProfile: null,
+        systemProgram: SystemProgram.programId,
       })
       .rpc({ commitment: "confirmed" });
 
     console.log("Move piece tx:", tx);
 
-    const board = await program.account.board.fetch(boardPda);
+    const board = await program.account.board.fetch(gamePda);
     assert.equal(
       destination,
       board.state[pieceIdx],
@@ -99,6 +215,36 @@ describe("Minimal chess game example", () => {
       false,
       "Board should start with white's turn and then change"
     );
+
+    // piece slot (5 bits) | destination - 1 (6 bits) | promotion (3 bits)
+    const history = await program.account.moveHistory.fetch(historyPda(gamePda));
+    assert.deepEqual(history.moves, [(pieceIdx << 9) | ((destination - 1) << 3)]);
+  });
+
+  it("Guest offers a draw and maker declines", async () => {
+    await program.methods
+      .offerDraw()
+      .accountsStrict({
+        player: guest.publicKey,
+        board: gamePda,
+      })
+      .signers([guest])
+      .rpc({ commitment: "confirmed" });
+
+    let board = await program.account.board.fetch(gamePda);
+    assert.ok(board.drawOffer?.equals(guest.publicKey), "Offer is pending");
+
+    await program.methods
+      .declineDraw()
+      .accountsStrict({
+        player: maker.publicKey,
+        board: gamePda,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    board = await program.account.board.fetch(gamePda);
+    assert.isNull(board.drawOffer, "Offer was declined");
+    assert.deepEqual(board.result, { ongoing: {} });
   });
 
   it("Resigns the game", async () => {
@@ -106,11 +252,29 @@ describe("Minimal chess game example", () => {
       .resign()
       .accountsStrict({
         player: maker.publicKey,
-        board: boardPda,
+        board: gamePda,
+        whiteProfile: null,
+        blackProfile: null,
       })
       .rpc({ commitment: "confirmed" });
 
     console.log("Resign tx:", tx);
+
+    const board = await program.account.board.fetch(gamePda);
+    assert.deepEqual(board.result, { blackWins: {} }, "White resigned");
+    assert.deepEqual(board.termination, { resignation: {} });
+
+    // The end of the game is announced as an event
+    const { meta } = await provider.connection.getTransaction(tx, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const parser = new anchor.EventParser(program.programId, program.coder);
+    const events = [...parser.parseLogs(meta.logMessages)];
+    assert.equal(events.length, 1);
+    assert.equal(events[0].name, "gameEnded");
+    assert.ok(events[0].data.board.equals(gamePda));
+    assert.deepEqual(events[0].data.result, { blackWins: {} });
   });
 
   it("Closes the board account", async () => {
@@ -118,7 +282,13 @@ describe("Minimal chess game example", () => {
       .close()
       .accountsStrict({
         maker: maker.publicKey,
-        board: boardPda,
+        board: gamePda,
+        history: historyPda(gamePda),
+        challenge: null,
+        vault: null,
+        mint: null,
+        makerTokens: null,
+        tokenProgram: null,
       })
       .rpc({ commitment: "confirmed" });
 
@@ -126,9 +296,796 @@ describe("Minimal chess game example", () => {
 
     // Fetching the board now should fail
     try {
-      await program.account.board.fetch(boardPda);
+      await program.account.board.fetch(gamePda);
     } catch (err) {
       console.log("Board account successfully closed:", err.toString());
     }
   });
+
+  it("Starts a board from a FEN position", async () => {
+    const drillSeed = new BN(54321);
+    const drillPda = boardPda(maker.publicKey, drillSeed);
+    const accounts = initAccounts(drillPda);
+
+    // Black is in check with white to move: rejected
+    try {
+      await program.methods
+        .initializeFromPosition(drillSeed, null, null, null, new BN(0), "4k3/8/8/8/8/8/8/4RK2 w - - 0 1")
+        .accountsStrict(accounts)
+        .rpc({ commitment: "confirmed" });
+      assert.fail("Invalid setup should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidSetup");
+    }
+
+    // K+R vs K drill, black to move
+    await program.methods
+      .initializeFromPosition(drillSeed, null, null, null, new BN(0), "8/8/4k3/8/8/8/8/R3K3 b - - 0 1")
+      .accountsStrict(accounts)
+      .rpc({ commitment: "confirmed" });
+
+    const board = await program.account.board.fetch(drillPda);
+    assert.equal(board.isWhiteTurn, false);
+    assert.equal(board.state[0], 1, "Rook on a1");
+    assert.equal(board.state[4], 5, "White king on e1");
+    assert.equal(board.state[28], 45, "Black king on e6");
+    assert.equal(board.state[8], 0, "Pawns are off the board");
+  });
+
+  it("Claims a draw by threefold repetition", async () => {
+    const drawSeed = new BN(777);
+    const drawPda = boardPda(maker.publicKey, drawSeed);
+    const claim = () =>
+      program.methods
+        .claimDraw()
+        .accountsStrict({
+          player: maker.publicKey,
+          board: drawPda,
+          history: historyPda(drawPda),
+          whiteProfile: null,
+          blackProfile: null,
+        })
+        .rpc({ commitment: "confirmed" });
+
+    // Black pays for its share of the move history
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(
+        guest.publicKey,
+        anchor.web3.LAMPORTS_PER_SOL
+      ),
+      "confirmed"
+    );
+
+    await program.methods
+      .initialize(drawSeed, guest.publicKey, null, null, new BN(0), false)
+      .accountsStrict(initAccounts(drawPda))
+      .rpc({ commitment: "confirmed" });
+
+    // Knights out and back twice: Nf3 Nf6 Ng1 Ng8
+    const knights: [boolean, number, number][] = [
+      [true, 6, 22],
+      [false, 30, 46],
+      [true, 6, 7],
+      [false, 30, 63],
+    ];
+    for (const [isWhite, pieceIdx, destination] of [...knights, ...knights]) {
+      // The start position has only occurred twice before the last move
+      if (pieceIdx === 30 && destination === 63) {
+        try {
+          await claim();
+          assert.fail("Draw claimed too early");
+        } catch (err) {
+          assert.include(err.toString(), "NoDrawToClaim");
+        }
+      }
+
+      const player = isWhite ? maker.publicKey : guest.publicKey;
+      await program.methods
+        .movePiece(pieceIdx, destination, null)
+        .accountsStrict({
+          player,
+          board: drawPda,
+          history: historyPda(drawPda),
+          whiteProfile: null,
+          blackProfile: null,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers(isWhite ? [] : [guest])
+        .rpc({ commitment: "confirmed" });
+    }
+
+    await claim();
+
+    const board = await program.account.board.fetch(drawPda);
+    assert.deepEqual(board.result, { draw: {} });
+    assert.deepEqual(board.termination, { repetition: {} });
+  });
+
+  it("Draws once neither side can mate", async () => {
+    const deadSeed = new BN(888);
+    const deadPda = boardPda(maker.publicKey, deadSeed);
+
+    // The black pawn on d2 checks the white king, which takes it
+    await program.methods
+      .initializeFromPosition(deadSeed, guest.publicKey, null, null, new BN(0), "4k3/8/8/8/8/8/3p4/4K3 w - - 0 1")
+      .accountsStrict(initAccounts(deadPda))
+      .rpc({ commitment: "confirmed" });
+
+    // White king (slot 4) takes on d2 (square 12)
+    await program.methods
+      .movePiece(4, 12, null)
+      .accountsStrict({
+        player: maker.publicKey,
+        board: deadPda,
+        history: historyPda(deadPda),
+        whiteProfile: null,
+        blackProfile: null,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    const board = await program.account.board.fetch(deadPda);
+    assert.deepEqual(board.result, { draw: {} });
+    assert.deepEqual(board.termination, { insufficientMaterial: {} });
+  });
+
+  it("Pays the wager to the winner", async () => {
+    const wagerSeed = new BN(999);
+    const wager = new BN(anchor.web3.LAMPORTS_PER_SOL / 10);
+    const wagerPda = boardPda(maker.publicKey, wagerSeed);
+
+    // A wagered game can't seat its guest without a deposit
+    try {
+      await program.methods
+        .initialize(wagerSeed, guest.publicKey, null, null, wager, false)
+        .accountsStrict(initAccounts(wagerPda))
+        .rpc({ commitment: "confirmed" });
+      assert.fail("The guest must join to deposit");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidWager");
+    }
+
+    await program.methods
+      .initialize(wagerSeed, null, null, null, wager, false)
+      .accountsStrict(initAccounts(wagerPda))
+      .rpc({ commitment: "confirmed" });
+
+    await program.methods
+      .join(guest.publicKey)
+      .accountsStrict({
+        maker: maker.publicKey,
+        board: wagerPda,
+        guest: guest.publicKey,
+        guestProfile: null,
+        challenge: null,
+        config: configPda,
+        mint: null,
+        guestTokens: null,
+        vault: null,
+        tokenProgram: null,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([guest])
+      .rpc({ commitment: "confirmed" });
+
+    let board = await program.account.board.fetch(wagerPda);
+    assert.ok(board.pot.eq(wager.muln(2)), "Both stakes are escrowed");
+
+    // The maker resigns
+    await program.methods
+      .resign()
+      .accountsStrict({
+        player: maker.publicKey,
+        board: wagerPda,
+        whiteProfile: null,
+        blackProfile: null,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    // Closing before paying out would keep the guest's winnings
+    try {
+      await program.methods
+        .close()
+        .accountsStrict({
+          maker: maker.publicKey,
+          board: wagerPda,
+          history: historyPda(wagerPda),
+          challenge: null,
+          vault: null,
+          mint: null,
+          makerTokens: null,
+          tokenProgram: null,
+        })
+        .rpc({ commitment: "confirmed" });
+      assert.fail("Close should wait for the payout");
+    } catch (err) {
+      assert.include(err.toString(), "WagerNotSettled");
+    }
+
+    const before = await provider.connection.getBalance(guest.publicKey);
+    const treasuryBefore = await provider.connection.getBalance(treasury.publicKey);
+    await program.methods
+      .settle()
+      .accountsStrict({
+        board: wagerPda,
+        maker: maker.publicKey,
+        guest: guest.publicKey,
+        mint: null,
+        vault: null,
+        makerTokens: null,
+        guestTokens: null,
+        config: configPda,
+        treasury: treasury.publicKey,
+        treasuryTokens: null,
+        tokenProgram: null,
+      })
+      .rpc({ commitment: "confirmed" });
+    const after = await provider.connection.getBalance(guest.publicKey);
+    const treasuryAfter = await provider.connection.getBalance(treasury.publicKey);
+    const fee = wager.muln(2).muln(FEE_BPS).divn(10_000).toNumber();
+    assert.equal(after - before, wager.muln(2).toNumber() - fee, "Guest takes the pot");
+    assert.equal(treasuryAfter - treasuryBefore, fee, "Treasury takes the fee");
+
+    board = await program.account.board.fetch(wagerPda);
+    assert.ok(board.pot.isZero());
+
+    await program.methods
+      .close()
+      .accountsStrict({
+        maker: maker.publicKey,
+        board: wagerPda,
+        history: historyPda(wagerPda),
+        challenge: null,
+        vault: null,
+        mint: null,
+        makerTokens: null,
+        tokenProgram: null,
+      })
+      .rpc({ commitment: "confirmed" });
+  });
+
+  it("Refunds a wager nobody took up", async () => {
+    const cancelSeed = new BN(1008);
+    const wager = new BN(anchor.web3.LAMPORTS_PER_SOL / 10);
+    const cancelPda = boardPda(maker.publicKey, cancelSeed);
+
+    await program.methods
+      .initialize(cancelSeed, null, null, null, wager, false)
+      .accountsStrict(initAccounts(cancelPda))
+      .rpc({ commitment: "confirmed" });
+
+    // Resigning before anyone joined cancels the board
+    await program.methods
+      .resign()
+      .accountsStrict({
+        player: maker.publicKey,
+        board: cancelPda,
+        whiteProfile: null,
+        blackProfile: null,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    const join = () =>
+      program.methods
+        .join(guest.publicKey)
+        .accountsStrict({
+          maker: maker.publicKey,
+          board: cancelPda,
+          guest: guest.publicKey,
+          challenge: null,
+          config: configPda,
+          guestProfile: null,
+          mint: null,
+          guestTokens: null,
+          vault: null,
+          tokenProgram: null,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([guest])
+        .rpc({ commitment: "confirmed" });
+    try {
+      await join();
+      assert.fail("A finished game can't be joined");
+    } catch (err) {
+      assert.include(err.toString(), "GameAlreadyOver");
+    }
+
+    // The whole stake goes back to the maker, fee free
+    const before = await provider.connection.getBalance(cancelPda);
+    await program.methods
+      .settle()
+      .accountsStrict({
+        board: cancelPda,
+        maker: maker.publicKey,
+        guest: null,
+        mint: null,
+        vault: null,
+        makerTokens: null,
+        guestTokens: null,
+        config: configPda,
+        treasury: treasury.publicKey,
+        treasuryTokens: null,
+        tokenProgram: null,
+      })
+      .rpc({ commitment: "confirmed" });
+    const after = await provider.connection.getBalance(cancelPda);
+    assert.equal(before - after, wager.toNumber(), "The stake is refunded");
+
+    // Nor after the payout, which would lock a new pot
+    try {
+      await join();
+      assert.fail("A settled game can't be joined");
+    } catch (err) {
+      assert.include(err.toString(), "GameAlreadyOver");
+    }
+  });
+
+  it("Escrows a token wager and refunds it on a draw", async () => {
+    const tokenSeed = new BN(1001);
+    const tokenPda = boardPda(maker.publicKey, tokenSeed);
+
+    // A community token, with a million base units for each player
+    const mint = await createMint(
+      provider.connection,
+      maker.payer,
+      maker.publicKey,
+      null,
+      6
+    );
+    const tokensOf = async (owner: PublicKey) => {
+      const account = await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        maker.payer,
+        mint,
+        owner
+      );
+      await mintTo(
+        provider.connection,
+        maker.payer,
+        mint,
+        account.address,
+        maker.payer,
+        1_000_000
+      );
+      return account.address;
+    };
+    const makerTokens = await tokensOf(maker.publicKey);
+    const guestTokens = await tokensOf(guest.publicKey);
+    const vault = getAssociatedTokenAddressSync(mint, tokenPda, true);
+    const balance = async (account: PublicKey) =>
+      Number((await getAccount(provider.connection, account)).amount);
+
+    const wager = new BN(250_000);
+    await program.methods
+      .initialize(tokenSeed, null, null, null, wager, false)
+      .accountsStrict(
+        initAccounts(tokenPda, {
+          mint,
+          makerTokens,
+          vault,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        })
+      )
+      .rpc({ commitment: "confirmed" });
+
+    await program.methods
+      .join(guest.publicKey)
+      .accountsStrict({
+        maker: maker.publicKey,
+        board: tokenPda,
+        guest: guest.publicKey,
+        guestProfile: null,
+        challenge: null,
+        config: configPda,
+        mint,
+        guestTokens,
+        vault,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([guest])
+      .rpc({ commitment: "confirmed" });
+
+    assert.equal(await balance(vault), 500_000, "Both stakes are in the vault");
+    assert.equal(await balance(guestTokens), 750_000);
+    const board = await program.account.board.fetch(tokenPda);
+    assert.ok(board.wagerMint?.equals(mint));
+
+    // Agreed draw
+    await program.methods
+      .offerDraw()
+      .accountsStrict({
+        player: guest.publicKey,
+        board: tokenPda,
+      })
+      .signers([guest])
+      .rpc({ commitment: "confirmed" });
+    await program.methods
+      .acceptDraw()
+      .accountsStrict({
+        player: maker.publicKey,
+        board: tokenPda,
+        whiteProfile: null,
+        blackProfile: null,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    await program.methods
+      .settle()
+      .accountsStrict({
+        board: tokenPda,
+        maker: maker.publicKey,
+        guest: guest.publicKey,
+        mint,
+        vault,
+        makerTokens,
+        guestTokens,
+        config: configPda,
+        treasury: treasury.publicKey,
+        // Draws are fee free
+        treasuryTokens: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    assert.equal(await balance(makerTokens), 1_000_000, "Maker's stake is back");
+    assert.equal(await balance(guestTokens), 1_000_000, "Guest's stake is back");
+
+    // Tokens sent to the settled vault can't keep it open
+    await transfer(
+      provider.connection,
+      maker.payer,
+      guestTokens,
+      vault,
+      guest,
+      1
+    );
+
+    // Closing the board closes its vault too, the stray token going to the maker
+    await program.methods
+      .close()
+      .accountsStrict({
+        maker: maker.publicKey,
+        board: tokenPda,
+        history: historyPda(tokenPda),
+        challenge: null,
+        vault,
+        mint,
+        makerTokens,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc({ commitment: "confirmed" });
+    assert.isNull(await provider.connection.getAccountInfo(vault));
+    assert.equal(await balance(makerTokens), 1_000_001);
+  });
+
+  it("Refuses token wagers in mints with transfer fees", async () => {
+    const feeSeed = new BN(1005);
+    const feePda = boardPda(maker.publicKey, feeSeed);
+
+    // A Token-2022 mint keeping 1% of every transfer
+    const mintKeypair = Keypair.generate();
+    const mint = mintKeypair.publicKey;
+    const mintLen = getMintLen([ExtensionType.TransferFeeConfig]);
+    await provider.sendAndConfirm(
+      new Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: maker.publicKey,
+          newAccountPubkey: mint,
+          space: mintLen,
+          lamports: await provider.connection.getMinimumBalanceForRentExemption(mintLen),
+          programId: TOKEN_2022_PROGRAM_ID,
+        }),
+        createInitializeTransferFeeConfigInstruction(
+          mint,
+          maker.publicKey,
+          maker.publicKey,
+          100,
+          BigInt(1_000),
+          TOKEN_2022_PROGRAM_ID
+        ),
+        createInitializeMintInstruction(mint, 6, maker.publicKey, null, TOKEN_2022_PROGRAM_ID)
+      ),
+      [mintKeypair],
+      { commitment: "confirmed" }
+    );
+    const makerTokens = (
+      await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        maker.payer,
+        mint,
+        maker.publicKey,
+        false,
+        "confirmed",
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      )
+    ).address;
+    await mintTo(
+      provider.connection,
+      maker.payer,
+      mint,
+      makerTokens,
+      maker.payer,
+      1_000_000,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    // The vault would end up short of the pot
+    try {
+      await program.methods
+        .initialize(feeSeed, null, null, null, new BN(250_000), false)
+        .accountsStrict(
+          initAccounts(feePda, {
+            mint,
+            makerTokens,
+            vault: getAssociatedTokenAddressSync(mint, feePda, true, TOKEN_2022_PROGRAM_ID),
+            tokenProgram: TOKEN_2022_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          })
+        )
+        .rpc({ commitment: "confirmed" });
+      assert.fail("Transfer fee mints can't be wagered");
+    } catch (err) {
+      assert.include(err.toString(), "TransferFeeMint");
+    }
+  });
+
+  it("Pauses new games", async () => {
+    const pausedSeed = new BN(1002);
+    const pausedPda = boardPda(maker.publicKey, pausedSeed);
+    // Created before the pause, still waiting for its guest
+    const waitingSeed = new BN(1006);
+    const waitingPda = boardPda(maker.publicKey, waitingSeed);
+    await program.methods
+      .initialize(waitingSeed, null, null, null, new BN(0), false)
+      .accountsStrict(initAccounts(waitingPda))
+      .rpc({ commitment: "confirmed" });
+    const setPaused = (paused: boolean, admin: Keypair | null = null) =>
+      program.methods
+        .setPaused(paused)
+        .accountsStrict({
+          admin: admin ? admin.publicKey : maker.publicKey,
+          config: configPda,
+        })
+        .signers(admin ? [admin] : [])
+        .rpc({ commitment: "confirmed" });
+
+    try {
+      await setPaused(true, guest);
+      assert.fail("Only the admin can pause");
+    } catch (err) {
+      assert.include(err.toString(), "Unauthorized");
+    }
+
+    await setPaused(true);
+    try {
+      await program.methods
+        .initialize(pausedSeed, null, null, null, new BN(0), false)
+        .accountsStrict(initAccounts(pausedPda))
+        .rpc({ commitment: "confirmed" });
+      assert.fail("No new games while paused");
+    } catch (err) {
+      assert.include(err.toString(), "Paused");
+    }
+
+    // Nor can waiting boards get listed or start
+    try {
+      await program.methods
+        .openChallenge([], 0)
+        .accountsStrict({
+          maker: maker.publicKey,
+          board: waitingPda,
+          challenge: PublicKey.findProgramAddressSync(
+            [Buffer.from("challenge"), waitingPda.toBuffer()],
+            program.programId
+          )[0],
+          config: configPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc({ commitment: "confirmed" });
+      assert.fail("No new challenges while paused");
+    } catch (err) {
+      assert.include(err.toString(), "Paused");
+    }
+    try {
+      await program.methods
+        .join(guest.publicKey)
+        .accountsStrict({
+          maker: maker.publicKey,
+          board: waitingPda,
+          guest: guest.publicKey,
+          challenge: null,
+          config: configPda,
+          guestProfile: null,
+          mint: null,
+          guestTokens: null,
+          vault: null,
+          tokenProgram: null,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([guest])
+        .rpc({ commitment: "confirmed" });
+      assert.fail("No games start while paused");
+    } catch (err) {
+      assert.include(err.toString(), "Paused");
+    }
+    await setPaused(false);
+  });
+
+  it("Rates both players when a rated game ends", async () => {
+    const ratedSeed = new BN(1003);
+    const ratedPda = boardPda(maker.publicKey, ratedSeed);
+    const profilePda = (player: PublicKey) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("profile"), player.toBuffer()],
+        program.programId
+      )[0];
+    const makerProfile = profilePda(maker.publicKey);
+    const guestProfile = profilePda(guest.publicKey);
+
+    // Profiles last across games, and deployments on a shared cluster
+    for (const [player, signers] of [
+      [maker.publicKey, []],
+      [guest.publicKey, [guest]],
+    ] as [PublicKey, Keypair[]][]) {
+      if (!(await program.account.playerProfile.fetchNullable(profilePda(player)))) {
+        await program.methods
+          .createProfile()
+          .accountsStrict({
+            player,
+            profile: profilePda(player),
+            systemProgram: SystemProgram.programId,
+          })
+          .signers(signers)
+          .rpc({ commitment: "confirmed" });
+      }
+    }
+    const whiteBefore = await program.account.playerProfile.fetch(makerProfile);
+    const blackBefore = await program.account.playerProfile.fetch(guestProfile);
+
+    await program.methods
+      .initialize(ratedSeed, guest.publicKey, null, null, new BN(0), true)
+      .accountsStrict(initAccounts(ratedPda, { makerProfile, guestProfile }))
+      .rpc({ commitment: "confirmed" });
+
+    const resign = (whiteProfile: PublicKey | null, blackProfile: PublicKey | null) =>
+      program.methods
+        .resign()
+        .accountsStrict({ player: guest.publicKey, board: ratedPda, whiteProfile, blackProfile })
+        .signers([guest])
+        .rpc({ commitment: "confirmed" });
+
+    // The game can't end without rating it
+    try {
+      await resign(makerProfile, null);
+      assert.fail("Both profiles are needed");
+    } catch (err) {
+      assert.include(err.toString(), "ProfileMissing");
+    }
+
+    await resign(makerProfile, guestProfile);
+
+    const white = await program.account.playerProfile.fetch(makerProfile);
+    const black = await program.account.playerProfile.fetch(guestProfile);
+    assert.isAbove(white.rating, whiteBefore.rating, "The winner gains points");
+    assert.isBelow(black.rating, blackBefore.rating, "The loser drops points");
+    assert.equal(white.wins, whiteBefore.wins + 1);
+    assert.equal(black.losses, blackBefore.losses + 1);
+    assert.equal(black.games, blackBefore.games + 1);
+
+    // The guest can only seat itself, the profile it brings is the one the game rates
+    const openSeed = new BN(1007);
+    const openPda = boardPda(maker.publicKey, openSeed);
+    await program.methods
+      .initialize(openSeed, null, null, null, new BN(0), true)
+      .accountsStrict(initAccounts(openPda, { makerProfile }))
+      .rpc({ commitment: "confirmed" });
+    try {
+      await program.methods
+        .join(Keypair.generate().publicKey)
+        .accountsStrict({
+          maker: maker.publicKey,
+          board: openPda,
+          guest: guest.publicKey,
+          challenge: null,
+          config: configPda,
+          guestProfile,
+          mint: null,
+          guestTokens: null,
+          vault: null,
+          tokenProgram: null,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([guest])
+        .rpc({ commitment: "confirmed" });
+      assert.fail("A profileless player can't be seated");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidPlayer");
+    }
+  });
+
+  it("Lists a challenge in the lobby until a friend takes it up", async () => {
+    const lobbySeed = new BN(1004);
+    const lobbyPda = boardPda(maker.publicKey, lobbySeed);
+    const [challengePda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("challenge"), lobbyPda.toBuffer()],
+      program.programId
+    );
+    const stranger = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(
+        stranger.publicKey,
+        anchor.web3.LAMPORTS_PER_SOL
+      ),
+      "confirmed"
+    );
+
+    await program.methods
+      .initialize(lobbySeed, null, null, null, new BN(0), false)
+      .accountsStrict(initAccounts(lobbyPda))
+      .rpc({ commitment: "confirmed" });
+
+    // Only the guest may join
+    await program.methods
+      .openChallenge([guest.publicKey], 0)
+      .accountsStrict({
+        maker: maker.publicKey,
+        board: lobbyPda,
+        challenge: challengePda,
+        config: configPda,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc({ commitment: "confirmed" });
+
+    // The lobby is every challenge account
+    const lobby = await program.account.challenge.all();
+    const listed = lobby.find(({ publicKey }) => publicKey.equals(challengePda));
+    assert.ok(listed?.account.board.equals(lobbyPda), "The challenge is listed");
+
+    const join = (player: Keypair, challenge: PublicKey | null) =>
+      program.methods
+        .join(player.publicKey)
+        .accountsStrict({
+          maker: maker.publicKey,
+          board: lobbyPda,
+          guest: player.publicKey,
+          guestProfile: null,
+          challenge,
+          config: configPda,
+          mint: null,
+          guestTokens: null,
+          vault: null,
+          tokenProgram: null,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([player])
+        .rpc({ commitment: "confirmed" });
+
+    try {
+      await join(stranger, null);
+      assert.fail("The challenge can't be bypassed");
+    } catch (err) {
+      assert.include(err.toString(), "ChallengeMissing");
+    }
+    try {
+      await join(stranger, challengePda);
+      assert.fail("Strangers aren't allowed");
+    } catch (err) {
+      assert.include(err.toString(), "NotAllowedToJoin");
+    }
+
+    await join(guest, challengePda);
+
+    const board = await program.account.board.fetch(lobbyPda);
+    assert.ok(board.guest?.equals(guest.publicKey));
+    assert.isFalse(board.inLobby);
+    assert.isNull(
+      await provider.connection.getAccountInfo(challengePda),
+      "The challenge left the lobby"
+    );
+  });
 });